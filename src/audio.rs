@@ -0,0 +1,248 @@
+use crate::{errors, image_format};
+
+// Anything shorter is an error page or a truncated download, not a song
+const MIN_DURATION_SECS: f64 = 5.0;
+// Consecutive frames required before we trust a frame sync
+const MIN_FRAMES: usize = 3;
+
+const BITRATES_V1_L1: [u32; 15] = [
+    0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+];
+const BITRATES_V1_L2: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+];
+const BITRATES_V1_L3: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const BITRATES_V2_L1: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+];
+const BITRATES_V2_L23: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+struct FrameHeader {
+    length: usize,
+    samples: u32,
+    sample_rate: u32,
+}
+
+impl FrameHeader {
+    fn parse(header: &[u8]) -> Option<FrameHeader> {
+        let [b0, b1, b2, _] = *header.get(..4)? else {
+            return None;
+        };
+        if b0 != 0xFF || b1 & 0xE0 != 0xE0 {
+            return None;
+        }
+        // 0: MPEG 2.5, 2: MPEG 2, 3: MPEG 1
+        let version = (b1 >> 3) & 0x03;
+        // 1: Layer III, 2: Layer II, 3: Layer I
+        let layer = (b1 >> 1) & 0x03;
+        if version == 1 || layer == 0 {
+            return None;
+        }
+
+        let bitrate_idx = usize::from(b2 >> 4);
+        let bitrates = match (version, layer) {
+            (3, 3) => &BITRATES_V1_L1,
+            (3, 2) => &BITRATES_V1_L2,
+            (3, _) => &BITRATES_V1_L3,
+            (_, 3) => &BITRATES_V2_L1,
+            _ => &BITRATES_V2_L23,
+        };
+        // Free format (0) and the forbidden index (15) are not supported
+        let bitrate = *bitrates.get(bitrate_idx).filter(|&&b| b != 0)? * 1000;
+
+        let sample_rate = match (b2 >> 2) & 0x03 {
+            0 => 44100,
+            1 => 48000,
+            2 => 32000,
+            _ => return None,
+        } >> match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        };
+        let padding = u32::from((b2 >> 1) & 0x01);
+
+        let (samples, length) = match layer {
+            3 => (384, (12 * bitrate / sample_rate + padding) * 4),
+            2 => (1152, 144 * bitrate / sample_rate + padding),
+            _ if version == 3 => (1152, 144 * bitrate / sample_rate + padding),
+            _ => (576, 72 * bitrate / sample_rate + padding),
+        };
+
+        Some(FrameHeader {
+            length: usize::try_from(length).ok()?,
+            samples,
+            sample_rate,
+        })
+    }
+}
+
+/// Size of the leading `ID3v2` tag, including its header and optional footer.
+fn id3v2_size(binary: &[u8]) -> usize {
+    if binary.len() < 10 || &binary[..3] != b"ID3" {
+        return 0;
+    }
+    // The tag size is stored as four 7-bit "syncsafe" bytes
+    let size = binary[6..10]
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 7) | usize::from(b & 0x7F));
+    let footer = if binary[5] & 0x10 == 0 { 0 } else { 10 };
+    10 + size + footer
+}
+
+/// Count the consecutive MPEG frames starting at `offset` and their total duration.
+fn walk_frames(binary: &[u8], offset: usize) -> (usize, f64) {
+    let mut offset = offset;
+    let mut frames = 0;
+    let mut duration = 0.0;
+    while let Some(frame) = binary.get(offset..).and_then(FrameHeader::parse) {
+        if offset + frame.length > binary.len() {
+            break;
+        }
+        frames += 1;
+        duration += f64::from(frame.samples) / f64::from(frame.sample_rate);
+        offset += frame.length;
+    }
+    (frames, duration)
+}
+
+/// Check that `binary` is an MP3 file and return its duration in seconds.
+pub fn probe_mp3(binary: &[u8]) -> Result<f64, errors::SheetError> {
+    if binary.is_empty() {
+        return Err(errors::SheetError::InvalidAudio(
+            "empty response".to_string(),
+        ));
+    }
+    let head = binary.iter().take(64).copied().collect::<Vec<_>>();
+    let head = String::from_utf8_lossy(&head);
+    let head = head.trim_start();
+    if head.starts_with('<') || head.starts_with('{') {
+        return Err(errors::SheetError::InvalidAudio(
+            "got an HTML or JSON body instead of audio".to_string(),
+        ));
+    }
+
+    // Find the first frame sync that is followed by enough valid frames
+    let start = id3v2_size(binary);
+    let (frames, duration) = (start..binary.len())
+        .filter(|&offset| binary[offset] == 0xFF)
+        .map(|offset| walk_frames(binary, offset))
+        .find(|(frames, _)| *frames >= MIN_FRAMES)
+        .ok_or(errors::SheetError::InvalidAudio(
+            "no MP3 frame found".to_string(),
+        ))?;
+    log::info!("Found {frames} MP3 frames, {duration:.1} seconds");
+
+    if duration < MIN_DURATION_SECS {
+        return Err(errors::SheetError::InvalidAudio(format!(
+            "too short ({duration:.1} seconds)"
+        )));
+    }
+    Ok(duration)
+}
+
+/// Metadata written into the ID3 tag of a downloaded track.
+pub struct TagInfo<'a> {
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub comment: String,
+    pub artwork: Option<&'a [u8]>,
+}
+
+/// Write ID3v2.4 tags into the MP3 file at `path`, replacing any existing tag.
+pub fn write_tags(path: &str, info: &TagInfo<'_>) -> anyhow::Result<()> {
+    use id3::TagLike;
+
+    let mut tag = id3::Tag::new();
+    tag.set_title(info.title.clone());
+    if let Some(artist) = &info.artist {
+        tag.set_artist(artist.clone());
+    }
+    if let Some(album) = &info.album {
+        tag.set_album(album.clone());
+    }
+    tag.add_frame(id3::frame::Comment {
+        lang: "chi".to_string(),
+        description: String::new(),
+        text: info.comment.clone(),
+    });
+    if let Some(artwork) = info.artwork {
+        if let Some(format) = image_format::ImageFormat::detect(artwork) {
+            tag.add_frame(id3::frame::Picture {
+                mime_type: format.mime().to_string(),
+                picture_type: id3::frame::PictureType::CoverFront,
+                description: String::new(),
+                data: artwork.to_vec(),
+            });
+        } else {
+            log::warn!("Unsupported artwork format, skipping cover art");
+        }
+    }
+    tag.write_to_path(path, id3::Version::Id3v24)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MPEG 1 Layer III at 128 kbit/s and 48 kHz: 384 bytes and 24 ms a frame
+    const FRAME_HEADER: [u8; 4] = [0xFF, 0xFB, 0x94, 0x00];
+    const FRAME_LEN: usize = 384;
+
+    fn frames(count: usize) -> Vec<u8> {
+        let mut frame = FRAME_HEADER.to_vec();
+        frame.resize(FRAME_LEN, 0);
+        frame.repeat(count)
+    }
+
+    fn error(binary: &[u8]) -> String {
+        match probe_mp3(binary) {
+            Err(errors::SheetError::InvalidAudio(message)) => message,
+            other => panic!("expected an invalid audio error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn duration_of_frames() {
+        let duration = probe_mp3(&frames(250)).unwrap();
+        assert!((duration - 6.0).abs() < 1e-9, "duration {duration}");
+    }
+
+    #[test]
+    fn skip_id3v2_tag() {
+        // A 20-byte tag whose content looks like a frame sync
+        let mut binary = b"ID3\x04\x00\x00\x00\x00\x00\x14".to_vec();
+        binary.extend([0xFF; 20]);
+        binary.extend(frames(250));
+        let duration = probe_mp3(&binary).unwrap();
+        assert!((duration - 6.0).abs() < 1e-9, "duration {duration}");
+    }
+
+    #[test]
+    fn reject_html_and_json_bodies() {
+        let html = b"\n<!DOCTYPE html><html><body>Not found</body></html>";
+        assert!(error(html).contains("HTML or JSON"));
+        let json = br#"  {"errcode": 40001, "errmsg": "invalid credential"}"#;
+        assert!(error(json).contains("HTML or JSON"));
+    }
+
+    #[test]
+    fn reject_empty_payload() {
+        assert!(error(&[]).contains("empty"));
+    }
+
+    #[test]
+    fn reject_payload_without_frames() {
+        assert!(error(&[0x00, 0xFF, 0xFB, 0x94, 0x00, 0x12]).contains("no MP3 frame"));
+    }
+
+    #[test]
+    fn reject_too_short_payload() {
+        // 100 frames are 2.4 seconds
+        assert!(error(&frames(100)).contains("too short"));
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::Write;
+
+use crate::{
+    config::PdfOptions,
+    manifest,
+    numeric::{to_u32, to_usize},
+    paths, pdf,
+};
+
+const COVER_TITLE_SIZE: f32 = 32.0;
+const HEADING_SIZE: f32 = 20.0;
+const ENTRY_SIZE: f32 = 12.0;
+const ENTRY_HEIGHT: f32 = 20.0;
+const ELLIPSIS: char = '…';
+
+/// The heading of the table of contents and the song count of the cover in
+/// the configured language.
+fn labels(language: &str, count: usize) -> (&'static str, String) {
+    match language {
+        "en" => ("Contents", format!("{count} songs")),
+        "zh-Hant" => ("目錄", format!("{count} 首詩歌")),
+        _ => ("目录", format!("{count} 首诗歌")),
+    }
+}
+
+/// Shorten the text with an ellipsis so that it is at most `width` wide.
+fn fit_text(text: &str, size: f32, width: f32) -> String {
+    if pdf::text_width(text, size) <= width {
+        return text.to_string();
+    }
+    let mut fitted = text.to_string();
+    while !fitted.is_empty() {
+        fitted.pop();
+        let shortened = format!("{}{ELLIPSIS}", fitted.trim_end());
+        if pdf::text_width(&shortened, size) <= width {
+            return shortened;
+        }
+    }
+    ELLIPSIS.to_string()
+}
+
+/// A song folder of the library.
+pub struct Song {
+    pub title: String,
+    pub folder: String,
+    /// Paths of the sheet pages in order
+    pub pages: Vec<String>,
+}
+
+impl Song {
+    /// Read the title and the pages of a song folder, preferring its manifest.
+    pub fn load(folder: &str) -> anyhow::Result<Song> {
+        let folder_name = std::path::Path::new(folder)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if let Ok(manifest) = manifest::Manifest::load(folder) {
+            if !manifest.pages.is_empty() {
+                return Ok(Song {
+                    title: Some(manifest.title)
+                        .filter(|title| !title.is_empty())
+                        .unwrap_or(folder_name),
+                    folder: folder.to_string(),
+                    pages: manifest
+                        .pages
+                        .iter()
+                        .map(|page| format!("{folder}/{}", page.file))
+                        .collect(),
+                });
+            }
+        }
+
+        // Older folders only have the numbered images
+        let mut pages = std::fs::read_dir(folder)?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let path = entry.path();
+                let number = path.file_stem()?.to_str()?.parse::<u32>().ok()?;
+                let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+                matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "gif" | "webp")
+                    .then(|| (number, path.to_string_lossy().to_string()))
+            })
+            .collect::<Vec<_>>();
+        pages.sort();
+        Ok(Song {
+            title: folder_name,
+            folder: folder.to_string(),
+            pages: pages.into_iter().map(|(_, path)| path).collect(),
+        })
+    }
+
+    /// Find a song by its folder path, its folder name under `output` or its title.
+    pub fn find(output: &str, query: &str) -> anyhow::Result<Song> {
+        if std::path::Path::new(query).is_dir() {
+            return Song::load(query);
+        }
+        let folder = format!("{output}/{query}");
+        if std::path::Path::new(&folder).is_dir() {
+            return Song::load(&folder);
+        }
+
+        let mut songs = paths::song_folders(output)
+            .iter()
+            .filter_map(|folder| Song::load(folder).ok())
+            .collect::<Vec<_>>();
+        let lowercase = query.to_lowercase();
+        if let Some(idx) = songs
+            .iter()
+            .position(|song| song.title.to_lowercase() == lowercase)
+        {
+            return Ok(songs.swap_remove(idx));
+        }
+        let mut matches = songs
+            .into_iter()
+            .filter(|song| song.title.to_lowercase().contains(&lowercase))
+            .collect::<Vec<_>>();
+        match matches.len() {
+            0 => Err(anyhow::anyhow!("No song matches {query}")),
+            1 => Ok(matches.remove(0)),
+            _ => Err(anyhow::anyhow!(
+                "{query} matches several songs: {:?}",
+                matches.iter().map(|song| &song.folder).collect::<Vec<_>>()
+            )),
+        }
+    }
+}
+
+fn entries_per_page(height: f32) -> usize {
+    to_usize(to_u32(f64::from(height / ENTRY_HEIGHT).floor().max(1.0)))
+}
+
+/// Combine the sheet pages of the songs into one PDF with a cover, a table of
+/// contents, bookmarks and page numbers. The labels are written in `language`.
+pub fn create_booklet(
+    songs: &[Song],
+    title: &str,
+    options: &PdfOptions,
+    language: &str,
+    path: &str,
+) -> anyhow::Result<()> {
+    let mut builder = pdf::PdfBuilder::new(options);
+    let (page_width, page_height) = builder.page_dimensions();
+    let margin = builder.margin();
+
+    let songs = songs
+        .iter()
+        .filter(|song| {
+            if song.pages.is_empty() {
+                log::warn!("Skipping {}, it has no sheet pages", song.folder);
+            }
+            !song.pages.is_empty()
+        })
+        .collect::<Vec<_>>();
+
+    // Reserve the cover and the table of contents, so that they can link to the songs
+    let cover_id = builder.alloc_page();
+    let per_page = entries_per_page(page_height - 2.0 * margin - 3.0 * HEADING_SIZE);
+    let contents_ids = (0..songs.len().div_ceil(per_page).max(1))
+        .map(|_| builder.alloc_page())
+        .collect::<Vec<_>>();
+
+    // Add the songs with their page numbers
+    let mut entries = Vec::new();
+    for song in &songs {
+        log::info!("Adding {}...", song.title);
+        let first_number = builder.page_count() + 1;
+        let mut first_id = None;
+        for page in &song.pages {
+            let binary = std::fs::read(page)?;
+            let number = (builder.page_count() + 1).to_string();
+            let page_id = builder.add_image_page(&binary, Some(&number))?;
+            first_id.get_or_insert(page_id);
+        }
+        if let Some(first_id) = first_id {
+            builder.add_outline(&song.title, first_id);
+            entries.push((song.title.clone(), first_number, first_id));
+        }
+    }
+
+    // Write the cover
+    let (heading, subtitle) = labels(language, entries.len());
+    builder.write_text_page(
+        cover_id,
+        &[
+            pdf::TextItem {
+                x: (page_width - pdf::text_width(title, COVER_TITLE_SIZE)) / 2.0,
+                y: page_height * 2.0 / 3.0,
+                text: title.to_string(),
+                size: COVER_TITLE_SIZE,
+                link: None,
+            },
+            pdf::TextItem {
+                x: (page_width - pdf::text_width(&subtitle, ENTRY_SIZE)) / 2.0,
+                y: page_height * 2.0 / 3.0 - COVER_TITLE_SIZE * 2.0,
+                text: subtitle,
+                size: ENTRY_SIZE,
+                link: None,
+            },
+        ],
+    );
+
+    // Write the table of contents
+    for (chunk_idx, contents_id) in contents_ids.iter().enumerate() {
+        let chunk = entries.chunks(per_page).nth(chunk_idx).unwrap_or_default();
+        let mut items = vec![pdf::TextItem {
+            text: heading.to_string(),
+            size: HEADING_SIZE,
+            x: margin,
+            y: page_height - margin - HEADING_SIZE,
+            link: None,
+        }];
+        let mut y = page_height - margin - HEADING_SIZE * 3.0;
+        for (idx, (title, number, page_id)) in chunk.iter().enumerate() {
+            let number = number.to_string();
+            // Leave the room of a glyph between the title and the page number
+            let title_width =
+                page_width - 2.0 * margin - pdf::text_width(&number, ENTRY_SIZE) - ENTRY_SIZE;
+            let text = format!("{}. {title}", chunk_idx * per_page + idx + 1);
+            items.push(pdf::TextItem {
+                text: fit_text(&text, ENTRY_SIZE, title_width),
+                size: ENTRY_SIZE,
+                x: margin,
+                y,
+                link: Some(*page_id),
+            });
+            items.push(pdf::TextItem {
+                x: page_width - margin - pdf::text_width(&number, ENTRY_SIZE),
+                text: number,
+                size: ENTRY_SIZE,
+                y,
+                link: Some(*page_id),
+            });
+            y -= ENTRY_HEIGHT;
+        }
+        builder.write_text_page(*contents_id, &items);
+    }
+
+    let info = pdf::DocumentInfo {
+        title: title.to_string(),
+        author: None,
+        source: entries
+            .iter()
+            .map(|(title, _, _)| title.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(&builder.finish(&info))?;
+    log::info!("Created {path} with {} songs", entries.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_long_titles() {
+        assert_eq!(
+            fit_text("1. Amazing Grace", 12.0, 200.0),
+            "1. Amazing Grace"
+        );
+        let fitted = fit_text("1. 奇异恩典何等甘甜我罪已得赦免", 12.0, 100.0);
+        assert_eq!(fitted, "1. 奇异恩典何…");
+        assert!(pdf::text_width(&fitted, 12.0) <= 100.0);
+        assert_eq!(fit_text("Amazing Grace", 12.0, 5.0), "…");
+    }
+}
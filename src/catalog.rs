@@ -0,0 +1,600 @@
+use std::{path::Path, sync::LazyLock};
+
+use rusqlite::OptionalExtension;
+
+use crate::{
+    booklet::Song,
+    lyrics::{self, Annotations},
+    manifest::Manifest,
+    metadata::Metadata,
+    paths,
+    search::{self, Document},
+};
+
+pub const CATALOG_FILE: &str = "catalog.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS songs (
+    id INTEGER PRIMARY KEY,
+    folder TEXT NOT NULL UNIQUE,
+    title TEXT NOT NULL,
+    song TEXT,
+    artist TEXT,
+    url TEXT,
+    video_url TEXT,
+    account TEXT,
+    author TEXT,
+    publish_time INTEGER,
+    key TEXT,
+    meter TEXT,
+    tempo INTEGER,
+    lyricist TEXT,
+    composer TEXT,
+    arranger TEXT,
+    lyrics TEXT,
+    indexed_time INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS assets (
+    id INTEGER PRIMARY KEY,
+    song_id INTEGER NOT NULL REFERENCES songs(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    file TEXT NOT NULL,
+    source_url TEXT,
+    hash TEXT
+);
+CREATE TABLE IF NOT EXISTS tags (
+    song_id INTEGER NOT NULL REFERENCES songs(id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (song_id, tag)
+);
+CREATE INDEX IF NOT EXISTS assets_song ON assets(song_id);
+CREATE VIRTUAL TABLE IF NOT EXISTS songs_fts USING fts5(
+    title, lyrics, title_pinyin, lyrics_pinyin, title_initials, lyrics_initials
+);
+";
+// Weights of the columns of songs_fts in the ranking, titles count most
+const RANK: &str = "bm25(songs_fts, 10.0, 1.0, 8.0, 1.0, 4.0, 0.5)";
+
+/// A file of a song folder.
+pub struct Asset {
+    /// One of `page`, `original`, `musicxml`, `audio`, `accompaniment`,
+    /// `pdf`, `video` and `lyrics`
+    pub kind: String,
+    pub file: String,
+    pub source_url: Option<String>,
+    /// Perceptual hash of a sheet page
+    pub hash: Option<String>,
+}
+
+/// What the catalog knows about a song folder.
+#[derive(Default)]
+pub struct Record {
+    /// Path of the folder relative to the output folder
+    pub folder: String,
+    pub title: String,
+    pub song: Option<String>,
+    pub artist: Option<String>,
+    pub url: Option<String>,
+    pub video_url: Option<String>,
+    pub account: Option<String>,
+    pub author: Option<String>,
+    /// Publish time of the article in seconds
+    pub publish_time: Option<i64>,
+    pub annotations: Annotations,
+    pub lyrics: Option<String>,
+    pub tags: Vec<String>,
+    pub assets: Vec<Asset>,
+}
+
+impl Record {
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            folder: self.folder.clone(),
+            title: self.title.clone(),
+            song: self.song.clone(),
+            artist: self.artist.clone(),
+            url: self.url.clone(),
+            video_url: self.video_url.clone(),
+            account: self.account.clone(),
+            author: self.author.clone(),
+            publish_time: self.publish_time,
+            annotations: self.annotations.to_json(),
+            lyrics: self.lyrics.clone(),
+            tags: self.tags.clone(),
+            assets: self.assets.iter().map(Asset::to_json).collect::<Vec<_>>(),
+        }
+    }
+
+    /// Gather the record of a song folder from its README, manifest and metadata.
+    pub fn scan(output: &str, folder: &str) -> anyhow::Result<Record> {
+        let relative = Path::new(folder)
+            .strip_prefix(output)
+            .unwrap_or(Path::new(folder))
+            .to_string_lossy()
+            .to_string();
+        let manifest = Manifest::load(folder).ok();
+        let metadata = Metadata::load(folder).ok();
+        let song = Song::load(folder)?;
+        let url = std::fs::read_to_string(format!("{folder}/README"))
+            .ok()
+            .map(|readme| readme.trim().to_string())
+            .filter(|url| !url.is_empty());
+
+        let mut record = Record {
+            folder: relative,
+            title: song.title.clone(),
+            url,
+            ..Record::default()
+        };
+        if let Some(metadata) = metadata {
+            record.title = Some(metadata.title)
+                .filter(|title| !title.is_empty())
+                .unwrap_or(record.title);
+            record.song = metadata.title_parts.get("song").cloned();
+            record.artist = metadata.title_parts.get("artist").cloned();
+            record.url = record
+                .url
+                .or_else(|| Some(metadata.url).filter(|url| !url.is_empty()));
+            record.tags = get_hashtags(metadata.body.as_deref().unwrap_or_default());
+            if let Some(account) = metadata.account.clone() {
+                if !record.tags.contains(&account) {
+                    record.tags.insert(0, account);
+                }
+            }
+            record.account = metadata.account;
+            record.author = metadata.author;
+            record.publish_time = metadata.publish_time;
+            record.annotations = metadata.annotations;
+            let lyrics_file = metadata
+                .lyrics
+                .unwrap_or_else(|| lyrics::LYRICS_FILE.to_string());
+            record.lyrics = std::fs::read_to_string(format!("{folder}/{lyrics_file}")).ok();
+            if record.lyrics.is_some() {
+                record.assets.push(Asset::new("lyrics", lyrics_file, None));
+            }
+        }
+
+        match manifest {
+            Some(manifest) => record.add_manifest_assets(manifest),
+            None => record.add_folder_assets(folder, &song),
+        }
+        Ok(record)
+    }
+
+    fn add_manifest_assets(&mut self, manifest: Manifest) {
+        for page in manifest.pages {
+            let source_url = Some(page.download_url).filter(|url| !url.is_empty());
+            for original in page.originals {
+                self.assets.push(Asset::new("original", original, None));
+            }
+            for musicxml in page.musicxml {
+                self.assets.push(Asset::new("musicxml", musicxml, None));
+            }
+            let mut asset = Asset::new("page", page.file, source_url);
+            asset.hash = Some(page.hash).filter(|hash| !hash.is_empty());
+            self.assets.push(asset);
+        }
+        for audio in manifest.audios {
+            let kind = if audio.accompaniment {
+                "accompaniment"
+            } else {
+                "audio"
+            };
+            self.assets
+                .push(Asset::new(kind, audio.file, Some(audio.url)));
+        }
+        if let Some(pdf) = manifest.pdf {
+            self.assets.push(Asset::new("pdf", pdf, None));
+        }
+        if let Some(video) = manifest.video {
+            self.assets
+                .push(Asset::new("video", video, manifest.video_url.clone()));
+        }
+        self.video_url = manifest.video_url;
+    }
+
+    /// Older folders have no manifest, only the numbered pages and the audio files.
+    fn add_folder_assets(&mut self, folder: &str, song: &Song) {
+        for page in &song.pages {
+            let file = page.strip_prefix(&format!("{folder}/")).unwrap_or(page);
+            self.assets.push(Asset::new("page", file.to_string(), None));
+        }
+        let Ok(entries) = std::fs::read_dir(folder) else {
+            return;
+        };
+        let mut audios = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| {
+                Path::new(name)
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("mp3"))
+            })
+            .collect::<Vec<_>>();
+        audios.sort();
+        for audio in audios {
+            self.assets.push(Asset::new("audio", audio, None));
+        }
+    }
+}
+
+impl Asset {
+    fn new(kind: &str, file: String, source_url: Option<String>) -> Asset {
+        Asset {
+            kind: kind.to_string(),
+            file,
+            source_url,
+            hash: None,
+        }
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            kind: self.kind.clone(),
+            file: self.file.clone(),
+            source_url: self.source_url.clone(),
+            hash: self.hash.clone(),
+        }
+    }
+}
+
+// A topic starts a word, so the key `1=#C` is not one, and starts with a
+// letter other than an ASCII one or with an English word
+static HASHTAG: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r"(?:^|[^\w=#＃])[#＃]((?:[\p{L}&&[^a-zA-Z]]|[a-zA-Z]{2})\w{0,18})")
+        .expect("valid hashtag pattern")
+});
+
+/// Find the topics such as `#赞美诗` written in the article.
+fn get_hashtags(body: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    for caps in HASHTAG.captures_iter(body) {
+        let tag = caps[1].to_string();
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// A line of `list` or `search`.
+pub struct Summary {
+    pub folder: String,
+    pub title: String,
+    pub account: Option<String>,
+    pub publish_time: Option<i64>,
+}
+
+impl Summary {
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            folder: self.folder.clone(),
+            title: self.title.clone(),
+            account: self.account.clone(),
+            publish_time: self.publish_time,
+        }
+    }
+}
+
+/// The `SQLite` index of the song folders, kept in the output folder.
+pub struct Catalog {
+    connection: rusqlite::Connection,
+    output: String,
+}
+
+impl Catalog {
+    pub fn open(output: &str) -> anyhow::Result<Catalog> {
+        std::fs::create_dir_all(output)?;
+        let connection = rusqlite::Connection::open(format!("{output}/{CATALOG_FILE}"))?;
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        connection.execute_batch(SCHEMA)?;
+        let mut catalog = Catalog {
+            connection,
+            output: output.to_string(),
+        };
+        catalog.fill_index()?;
+        Ok(catalog)
+    }
+
+    /// Add the songs missing from the full-text index, such as those recorded
+    /// before it existed.
+    fn fill_index(&mut self) -> anyhow::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "SELECT id, title, song, artist, lyrics FROM songs
+                WHERE id NOT IN (SELECT rowid FROM songs_fts)",
+            )?;
+            let rows = statement
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (id, title, song, artist, lyrics) in rows {
+                let titles = [Some(title), song, artist];
+                index(&transaction, id, &titles, lyrics.as_deref())?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Record the song folder, replacing what was known about it.
+    pub fn update(&mut self, folder: &str) -> anyhow::Result<()> {
+        let record = Record::scan(&self.output, folder)?;
+        let transaction = self.connection.transaction()?;
+        insert(&transaction, &record)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Rebuild the catalog from the song folders and return how many there are.
+    pub fn reindex(&mut self) -> anyhow::Result<usize> {
+        let folders = paths::song_folders(&self.output);
+        let mut records = Vec::new();
+        for folder in &folders {
+            match Record::scan(&self.output, folder) {
+                Ok(record) => records.push(record),
+                Err(e) => log::warn!("Skipping {folder}: {e}"),
+            }
+        }
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM songs_fts", [])?;
+        transaction.execute("DELETE FROM songs", [])?;
+        for record in &records {
+            insert(&transaction, record)?;
+        }
+        transaction.commit()?;
+        Ok(records.len())
+    }
+
+    pub fn list(&self) -> anyhow::Result<Vec<Summary>> {
+        self.summaries(
+            "SELECT folder, title, account, publish_time FROM songs ORDER BY folder",
+            [],
+        )
+    }
+
+    /// Find the songs matching the words of the query, or its pinyin or
+    /// initials, in their titles and lyrics, best matches first. They are
+    /// followed by the songs whose folder, credits, account or tags contain it.
+    pub fn search(&self, query: &str) -> anyhow::Result<Vec<Summary>> {
+        let mut summaries = match search::expression(query) {
+            Some(expression) => self.summaries(
+                &format!(
+                    "SELECT songs.folder, songs.title, songs.account, songs.publish_time FROM songs_fts
+                    JOIN songs ON songs.id = songs_fts.rowid
+                    WHERE songs_fts MATCH ?1 ORDER BY {RANK}"
+                ),
+                [expression],
+            )?,
+            None => Vec::new(),
+        };
+        for summary in self.search_text(query)? {
+            if !summaries.iter().any(|other| other.folder == summary.folder) {
+                summaries.push(summary);
+            }
+        }
+        Ok(summaries)
+    }
+
+    fn search_text(&self, query: &str) -> anyhow::Result<Vec<Summary>> {
+        let pattern = format!("%{}%", query.trim());
+        self.summaries(
+            "SELECT folder, title, account, publish_time FROM songs
+            WHERE folder LIKE ?1 OR title LIKE ?1 OR artist LIKE ?1 OR account LIKE ?1 OR author LIKE ?1
+                OR lyricist LIKE ?1 OR composer LIKE ?1 OR lyrics LIKE ?1
+                OR id IN (SELECT song_id FROM tags WHERE tag LIKE ?1)
+            ORDER BY folder",
+            [pattern],
+        )
+    }
+
+    fn summaries<P: rusqlite::Params>(&self, sql: &str, params: P) -> anyhow::Result<Vec<Summary>> {
+        let mut statement = self.connection.prepare(sql)?;
+        let rows = statement.query_map(params, |row| {
+            Ok(Summary {
+                folder: row.get(0)?,
+                title: row.get(1)?,
+                account: row.get(2)?,
+                publish_time: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Look up a song by its folder relative to the output folder.
+    pub fn get(&self, folder: &str) -> anyhow::Result<Option<Record>> {
+        let id = self
+            .connection
+            .query_row("SELECT id FROM songs WHERE folder = ?1", [folder], |row| {
+                row.get::<_, i64>(0)
+            })
+            .optional()?;
+        id.map(|id| self.load(id)).transpose()
+    }
+
+    /// Look up a song by its folder under the output folder or its title.
+    pub fn show(&self, query: &str) -> anyhow::Result<Record> {
+        let folder = Path::new(query)
+            .strip_prefix(&self.output)
+            .unwrap_or(Path::new(query))
+            .to_string_lossy()
+            .to_string();
+        let mut statement = self
+            .connection
+            .prepare("SELECT id FROM songs WHERE folder = ?1 OR title = ?2 OR song = ?2")?;
+        let mut ids = statement
+            .query_map([&folder, query], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if ids.is_empty() {
+            let mut statement = self
+                .connection
+                .prepare("SELECT id FROM songs WHERE title LIKE ?1")?;
+            ids = statement
+                .query_map([format!("%{query}%")], |row| row.get::<_, i64>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+        }
+        match ids.as_slice() {
+            [] => Err(anyhow::anyhow!("No song matches {query}")),
+            [id] => self.load(*id),
+            _ => Err(anyhow::anyhow!(
+                "{query} matches {} songs, try `search`",
+                ids.len()
+            )),
+        }
+    }
+
+    fn load(&self, id: i64) -> anyhow::Result<Record> {
+        let mut record = self.connection.query_row(
+            "SELECT folder, title, song, artist, url, video_url, account, author, publish_time,
+                key, meter, tempo, lyricist, composer, arranger, lyrics
+            FROM songs WHERE id = ?1",
+            [id],
+            |row| {
+                Ok(Record {
+                    folder: row.get(0)?,
+                    title: row.get(1)?,
+                    song: row.get(2)?,
+                    artist: row.get(3)?,
+                    url: row.get(4)?,
+                    video_url: row.get(5)?,
+                    account: row.get(6)?,
+                    author: row.get(7)?,
+                    publish_time: row.get(8)?,
+                    annotations: Annotations {
+                        key: row.get(9)?,
+                        meter: row.get(10)?,
+                        tempo: row.get(11)?,
+                        lyricist: row.get(12)?,
+                        composer: row.get(13)?,
+                        arranger: row.get(14)?,
+                    },
+                    lyrics: row.get(15)?,
+                    ..Record::default()
+                })
+            },
+        )?;
+        let mut statement = self
+            .connection
+            .prepare("SELECT tag FROM tags WHERE song_id = ?1 ORDER BY rowid")?;
+        record.tags = statement
+            .query_map([id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let mut statement = self.connection.prepare(
+            "SELECT kind, file, source_url, hash FROM assets WHERE song_id = ?1 ORDER BY id",
+        )?;
+        record.assets = statement
+            .query_map([id], |row| {
+                Ok(Asset {
+                    kind: row.get(0)?,
+                    file: row.get(1)?,
+                    source_url: row.get(2)?,
+                    hash: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(record)
+    }
+}
+
+/// Add a song to the full-text index, `titles` are its title and the parts of it.
+fn index(
+    connection: &rusqlite::Connection,
+    id: i64,
+    titles: &[Option<String>],
+    lyrics: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut title = String::new();
+    for part in titles.iter().flatten() {
+        if !title.contains(part.as_str()) {
+            title = format!("{title} {part}");
+        }
+    }
+    let document = Document::new(&title, lyrics.unwrap_or_default());
+    connection.execute(
+        "INSERT INTO songs_fts (rowid, title, lyrics, title_pinyin, lyrics_pinyin,
+            title_initials, lyrics_initials)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            id,
+            document.title,
+            document.lyrics,
+            document.title_pinyin,
+            document.lyrics_pinyin,
+            document.title_initials,
+            document.lyrics_initials,
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert(transaction: &rusqlite::Transaction, record: &Record) -> anyhow::Result<()> {
+    transaction.execute(
+        "DELETE FROM songs_fts WHERE rowid IN (SELECT id FROM songs WHERE folder = ?1)",
+        [&record.folder],
+    )?;
+    transaction.execute("DELETE FROM songs WHERE folder = ?1", [&record.folder])?;
+    let annotations = &record.annotations;
+    transaction.execute(
+        "INSERT INTO songs (folder, title, song, artist, url, video_url, account, author,
+            publish_time, key, meter, tempo, lyricist, composer, arranger, lyrics, indexed_time)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+        rusqlite::params![
+            record.folder,
+            record.title,
+            record.song,
+            record.artist,
+            record.url,
+            record.video_url,
+            record.account,
+            record.author,
+            record.publish_time,
+            annotations.key,
+            annotations.meter,
+            annotations.tempo,
+            annotations.lyricist,
+            annotations.composer,
+            annotations.arranger,
+            record.lyrics,
+            chrono::Local::now().timestamp(),
+        ],
+    )?;
+    let id = transaction.last_insert_rowid();
+    let titles = [
+        Some(record.title.clone()),
+        record.song.clone(),
+        record.artist.clone(),
+    ];
+    index(transaction, id, &titles, record.lyrics.as_deref())?;
+    for tag in &record.tags {
+        transaction.execute(
+            "INSERT OR IGNORE INTO tags (song_id, tag) VALUES (?1, ?2)",
+            rusqlite::params![id, tag],
+        )?;
+    }
+    for asset in &record.assets {
+        transaction.execute(
+            "INSERT INTO assets (song_id, kind, file, source_url, hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![id, asset.kind, asset.file, asset.source_url, asset.hash],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashtags() {
+        let body = "#赞美诗 #诗歌 #赞美诗\n1=#C 4/4 C#调 ＃敬拜，#worship #A #7 a#b";
+        assert_eq!(get_hashtags(body), ["赞美诗", "诗歌", "敬拜", "worship"]);
+    }
+}
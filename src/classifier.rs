@@ -0,0 +1,403 @@
+use image::{GenericImageView, GrayImage};
+
+use crate::numeric::{count_to_f64, to_usize};
+
+// Luma below this is ink, above WHITE_LUMA is paper
+const INK_LUMA: u8 = 128;
+const WHITE_LUMA: u8 = 200;
+// A row belongs to a staff line if this much of it is ink
+const STAFF_LINE_COVERAGE: f64 = 0.5;
+// A QR code covering this much of the image is the whole point of the image
+const QR_AREA_RATIO: f64 = 0.2;
+// Score of an image before any sign of a score, below the default threshold
+const BASE_SCORE: f64 = 0.2;
+// A line of numbered notation has at least this many glyphs, most of them
+// narrow like digits and of the same height
+const MIN_JIANPU_GLYPHS: usize = 6;
+const JIANPU_GLYPH_RATIO: f64 = 0.7;
+// Lines of numbered notation needed to take the image for a score
+const MIN_JIANPU_LINES: usize = 2;
+
+/// The verdict of the classifier for one image.
+pub struct Classification {
+    /// From 0.0 (certainly not a score) to 1.0 (certainly a score)
+    pub score: f64,
+    pub reason: String,
+}
+
+fn ratio(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count_to_f64(count) / count_to_f64(total)
+    }
+}
+
+/// Count groups of five evenly spaced horizontal lines.
+fn count_staves(gray: &GrayImage) -> usize {
+    let (width, height) = gray.dimensions();
+    let width = to_usize(width);
+
+    // Collect the center of every run of consecutive line rows
+    let mut lines = Vec::new();
+    let mut run_start = None;
+    for (y, row) in (0u32..).zip(gray.rows()) {
+        let ink = row.filter(|p| p.0[0] < INK_LUMA).count();
+        let is_line = ratio(ink, width) >= STAFF_LINE_COVERAGE;
+        match (is_line, run_start) {
+            (true, None) => run_start = Some(y),
+            (false, Some(start)) => {
+                lines.push(f64::from(start + y - 1) / 2.0);
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    // Look for five lines with four similar gaps
+    let max_gap = f64::from(height) / 20.0;
+    let mut staves = 0;
+    let mut idx = 0;
+    while idx + 5 <= lines.len() {
+        let gaps = lines[idx..idx + 5]
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect::<Vec<_>>();
+        let mean = gaps.iter().sum::<f64>() / 4.0;
+        let even = gaps.iter().all(|gap| (gap - mean).abs() <= mean * 0.25);
+        if even && mean >= 3.0 && mean <= max_gap {
+            staves += 1;
+            idx += 5;
+        } else {
+            idx += 1;
+        }
+    }
+    staves
+}
+
+/// Runs of consecutive indexes for which `has_ink` holds, as (first, last).
+fn ink_runs(len: u32, has_ink: impl Fn(u32) -> bool) -> Vec<(u32, u32)> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for idx in 0..len {
+        match (has_ink(idx), start) {
+            (true, None) => start = Some(idx),
+            (false, Some(first)) => {
+                runs.push((first, idx - 1));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = start {
+        runs.push((first, len - 1));
+    }
+    runs
+}
+
+/// Count the text lines that look like numbered musical notation (jianpu):
+/// rows of narrow glyphs of one height, like digits, rather than the square
+/// glyphs of Chinese text.
+fn count_jianpu_lines(gray: &GrayImage) -> usize {
+    let (width, height) = gray.dimensions();
+    let is_ink = |x: u32, y: u32| gray.get_pixel(x, y).0[0] < INK_LUMA;
+    let max_line_height = (height / 8).max(8);
+    ink_runs(height, |y| (0..width).any(|x| is_ink(x, y)))
+        .into_iter()
+        .filter(|(top, bottom)| (6..=max_line_height).contains(&(bottom - top + 1)))
+        .filter(|(top, bottom)| {
+            // The width and the height of the ink of every glyph of the line
+            let glyphs = ink_runs(width, |x| (*top..=*bottom).any(|y| is_ink(x, y)))
+                .into_iter()
+                .filter_map(|(left, right)| {
+                    let rows = ink_runs(bottom - top + 1, |dy| {
+                        (left..=right).any(|x| is_ink(x, top + dy))
+                    });
+                    let glyph_top = rows.first()?.0;
+                    let glyph_bottom = rows.last()?.1;
+                    Some((right - left + 1, glyph_bottom - glyph_top + 1))
+                })
+                .collect::<Vec<_>>();
+            if glyphs.len() < MIN_JIANPU_GLYPHS {
+                return false;
+            }
+            let mut heights = glyphs.iter().map(|glyph| glyph.1).collect::<Vec<_>>();
+            heights.sort_unstable();
+            let median = f64::from(heights[heights.len() / 2]);
+            let digits = glyphs
+                .iter()
+                .filter(|(width, height)| {
+                    let aspect = f64::from(*width) / f64::from(*height);
+                    (0.2..=0.8).contains(&aspect)
+                        && (f64::from(*height) - median).abs() <= median * 0.2
+                })
+                .count();
+            ratio(digits, glyphs.len()) >= JIANPU_GLYPH_RATIO
+        })
+        .count()
+}
+
+/// Find QR finder patterns (1:1:3:1:1 dark/light runs) and return the
+/// fraction of the image covered by the code, if one is found.
+fn find_qr_area(gray: &GrayImage) -> Option<f64> {
+    let (width, height) = gray.dimensions();
+    // (x, y, module size) of every row that crosses a finder pattern center
+    let mut hits = Vec::<(f64, f64, f64)>::new();
+    for (y, row) in (0u32..).zip(gray.rows()) {
+        let mut runs = Vec::<(bool, u32, u32)>::new();
+        for (x, pixel) in (0u32..).zip(row) {
+            let dark = pixel.0[0] < INK_LUMA;
+            match runs.last_mut() {
+                Some((last_dark, _, len)) if *last_dark == dark => *len += 1,
+                _ => runs.push((dark, x, 1)),
+            }
+        }
+        for window in runs.windows(5) {
+            if !window[0].0 {
+                continue;
+            }
+            let total = window.iter().map(|run| run.2).sum::<u32>();
+            if total < 7 {
+                continue;
+            }
+            let module = f64::from(total) / 7.0;
+            let expected = [1.0, 1.0, 3.0, 1.0, 1.0];
+            let matched = window.iter().zip(expected).all(|(run, modules)| {
+                (f64::from(run.2) - module * modules).abs() < module * modules * 0.5
+            });
+            if matched {
+                let center = f64::from(window[2].1) + f64::from(window[2].2) / 2.0;
+                hits.push((center, f64::from(y), module));
+            }
+        }
+    }
+
+    // Group the hits into finder patterns
+    let mut patterns = Vec::<(f64, f64, f64, usize)>::new();
+    for (x, y, module) in hits {
+        if let Some(pattern) = patterns.iter_mut().find(|(px, py, pm, _)| {
+            (px - x).abs() < pm * 2.0 && (py - y).abs() < pm * 4.0 && (pm - module).abs() < pm * 0.5
+        }) {
+            pattern.3 += 1;
+        } else {
+            patterns.push((x, y, module, 1));
+        }
+    }
+    // The center of a finder pattern is three modules tall
+    let patterns = patterns
+        .into_iter()
+        .filter(|(_, _, module, count)| count_to_f64(*count) >= (module * 1.5).max(2.0))
+        .collect::<Vec<_>>();
+    if patterns.len() < 3 {
+        return None;
+    }
+
+    let margin = patterns.iter().map(|p| p.2).fold(0.0, f64::max) * 3.5;
+    let min_x = patterns.iter().map(|p| p.0).fold(f64::MAX, f64::min) - margin;
+    let max_x = patterns.iter().map(|p| p.0).fold(f64::MIN, f64::max) + margin;
+    let min_y = patterns.iter().map(|p| p.1).fold(f64::MAX, f64::min) - margin;
+    let max_y = patterns.iter().map(|p| p.1).fold(f64::MIN, f64::max) + margin;
+    Some((max_x - min_x) * (max_y - min_y) / (f64::from(width) * f64::from(height)))
+}
+
+/// Decide whether a downloaded image is a page of a score from its content.
+pub fn classify(binary: &[u8]) -> Classification {
+    let image = match image::load_from_memory(binary) {
+        Ok(image) => image,
+        Err(e) => {
+            return Classification {
+                score: 0.0,
+                reason: format!("cannot decode image: {e}"),
+            }
+        }
+    };
+    let (width, height) = image.dimensions();
+    let rgb = image.to_rgb8();
+    let gray = image.to_luma8();
+    let total = gray.pixels().len();
+
+    if let Some(area) = find_qr_area(&gray).filter(|area| *area >= QR_AREA_RATIO) {
+        return Classification {
+            score: 0.0,
+            reason: format!("QR code covering {:.0}% of the image", area * 100.0),
+        };
+    }
+
+    let ink = ratio(gray.pixels().filter(|p| p.0[0] < INK_LUMA).count(), total);
+    let white = ratio(gray.pixels().filter(|p| p.0[0] > WHITE_LUMA).count(), total);
+    let midtone = 1.0 - ink - white;
+    let colorful = ratio(
+        rgb.pixels()
+            .filter(|p| {
+                let max = p.0.iter().max().copied().unwrap_or(0);
+                let min = p.0.iter().min().copied().unwrap_or(0);
+                max - min > 60
+            })
+            .count(),
+        total,
+    );
+    let staves = count_staves(&gray);
+    let jianpu_lines = if staves == 0 {
+        count_jianpu_lines(&gray)
+    } else {
+        0
+    };
+
+    // Only staves or numbered notation make an image a score, the rest
+    // tells apart clean pages from photos and banners
+    let mut score = BASE_SCORE;
+    let mut reasons = Vec::new();
+    if staves > 0 {
+        score += 0.5;
+        reasons.push(format!("{staves} staves"));
+    } else if jianpu_lines >= MIN_JIANPU_LINES {
+        score += 0.4;
+        reasons.push(format!("{jianpu_lines} lines of numbered notation"));
+    } else {
+        reasons.push("no staves or numbered notation".to_string());
+    }
+    if white >= 0.75 {
+        score += 0.15;
+        reasons.push(format!("white background {:.0}%", white * 100.0));
+    } else if white < 0.5 {
+        score -= 0.3;
+        reasons.push(format!("little white background {:.0}%", white * 100.0));
+    }
+    if ink < 0.01 {
+        score -= 0.3;
+        reasons.push("almost blank".to_string());
+    } else if ink > 0.4 {
+        score -= 0.2;
+        reasons.push(format!("too much ink {:.0}%", ink * 100.0));
+    }
+    if midtone > 0.3 {
+        score -= 0.3;
+        reasons.push(format!("photo-like tones {:.0}%", midtone * 100.0));
+    }
+    if colorful > 0.15 {
+        score -= 0.3;
+        reasons.push(format!("colorful {:.0}%", colorful * 100.0));
+    }
+    if staves == 0 && f64::from(height) < f64::from(width) * 0.5 {
+        score -= 0.2;
+        reasons.push("banner shape".to_string());
+    }
+
+    Classification {
+        score: score.clamp(0.0, 1.0),
+        reason: reasons.join(", "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{Luma, Rgb, RgbImage};
+
+    use super::*;
+
+    const WIDTH: u32 = 400;
+    const HEIGHT: u32 = 560;
+
+    fn encode(image: impl Into<image::DynamicImage>) -> Vec<u8> {
+        let mut png = Cursor::new(Vec::new());
+        image
+            .into()
+            .write_to(&mut png, image::ImageFormat::Png)
+            .expect("encode png");
+        png.into_inner()
+    }
+
+    fn blank() -> GrayImage {
+        GrayImage::from_pixel(WIDTH, HEIGHT, Luma([255]))
+    }
+
+    fn fill(image: &mut GrayImage, x: u32, y: u32, width: u32, height: u32) {
+        for dy in 0..height {
+            for dx in 0..width {
+                image.put_pixel(x + dx, y + dy, Luma([0]));
+            }
+        }
+    }
+
+    #[test]
+    fn staff_lines_are_a_score() {
+        let mut image = blank();
+        for staff in 0..4 {
+            for line in 0..5 {
+                fill(&mut image, 20, 60 + staff * 120 + line * 10, WIDTH - 40, 1);
+            }
+        }
+        let classification = classify(&encode(image));
+        assert!(classification.score >= 0.5, "{}", classification.reason);
+        assert!(classification.reason.contains("4 staves"));
+    }
+
+    #[test]
+    fn numbered_notation_is_a_score() {
+        let mut image = blank();
+        for line in 0..6 {
+            // Digits and bar lines, each 7 pixels wide and 14 tall
+            for glyph in 0..16 {
+                fill(&mut image, 20 + glyph * 22, 40 + line * 80, 7, 14);
+            }
+        }
+        let classification = classify(&encode(image));
+        assert!(classification.score >= 0.5, "{}", classification.reason);
+        assert!(classification.reason.contains("numbered notation"));
+    }
+
+    #[test]
+    fn square_text_is_not_a_score() {
+        let mut image = blank();
+        for line in 0..6 {
+            for glyph in 0..16 {
+                fill(&mut image, 20 + glyph * 22, 40 + line * 80, 16, 16);
+            }
+        }
+        let classification = classify(&encode(image));
+        assert!(classification.score < 0.5, "{}", classification.reason);
+    }
+
+    #[test]
+    fn qr_code_is_not_a_score() {
+        let mut image = blank();
+        // Three finder patterns of 7 modules of 12 pixels
+        for (x, y) in [(40, 40), (256, 40), (40, 256)] {
+            fill(&mut image, x, y, 84, 84);
+            for dy in 12..72 {
+                for dx in 12..72 {
+                    image.put_pixel(x + dx, y + dy, Luma([255]));
+                }
+            }
+            fill(&mut image, x + 24, y + 24, 36, 36);
+        }
+        let classification = classify(&encode(image));
+        assert!(classification.score.abs() < f64::EPSILON);
+        assert!(classification.reason.starts_with("QR code"));
+    }
+
+    #[test]
+    fn photo_is_not_a_score() {
+        let photo = RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+            Rgb([
+                u8::try_from(x % 256).unwrap_or(0),
+                120,
+                u8::try_from(y % 256).unwrap_or(0),
+            ])
+        });
+        let classification = classify(&encode(photo));
+        assert!(classification.score < 0.5, "{}", classification.reason);
+
+        let grey = GrayImage::from_pixel(WIDTH, HEIGHT, Luma([150]));
+        let classification = classify(&encode(grey));
+        assert!(classification.score < 0.5, "{}", classification.reason);
+    }
+
+    #[test]
+    fn blank_page_is_not_a_score() {
+        let classification = classify(&encode(blank()));
+        assert!(classification.score < 0.5, "{}", classification.reason);
+    }
+}
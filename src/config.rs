@@ -0,0 +1,554 @@
+use crate::{image_format::ImageFormat, pdf::PageSize};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Rules deciding which article images are sheet candidates.
+#[derive(Clone)]
+pub struct ImageRules {
+    /// Regexes that the image URL must all match
+    pub url_patterns: Vec<regex::Regex>,
+    pub formats: Vec<ImageFormat>,
+    pub min_width: u32,
+    pub min_height: u32,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// Allowed range of height / width
+    pub min_aspect_ratio: Option<f64>,
+    pub max_aspect_ratio: Option<f64>,
+    /// CSS selector of the images to consider
+    pub region: String,
+    /// Number of candidates to drop at the beginning and the end of the article
+    pub skip_first: usize,
+    pub skip_last: usize,
+    /// Minimum classifier score for a downloaded candidate to be kept
+    pub min_sheet_score: f64,
+    /// Images whose perceptual hashes differ by at most this many bits out of
+    /// 256 are the same
+    pub duplicate_distance: u32,
+    /// Images found in this many other articles are skipped, 0 to disable
+    pub blocklist_min_articles: usize,
+}
+
+impl Default for ImageRules {
+    fn default() -> Self {
+        ImageRules {
+            url_patterns: [r"mmbiz\.qpic\.cn", "from=appmsg", "#imgIndex="]
+                .into_iter()
+                .map(|pattern| regex::Regex::new(pattern).expect("valid built-in pattern"))
+                .collect(),
+            formats: vec![
+                ImageFormat::Png,
+                ImageFormat::Jpeg,
+                ImageFormat::Gif,
+                ImageFormat::Webp,
+            ],
+            min_width: 500,
+            min_height: 500,
+            max_width: None,
+            max_height: None,
+            min_aspect_ratio: None,
+            max_aspect_ratio: None,
+            region: "#js_content img, .rich_media_content img, img".to_string(),
+            skip_first: 0,
+            skip_last: 0,
+            min_sheet_score: 0.5,
+            duplicate_distance: 12,
+            blocklist_min_articles: 3,
+        }
+    }
+}
+
+impl ImageRules {
+    fn from_json(value: &json::JsonValue, base: &ImageRules) -> anyhow::Result<ImageRules> {
+        let url_patterns = if value["url_patterns"].is_array() {
+            get_strings(&value["url_patterns"])
+                .iter()
+                .map(|pattern| regex::Regex::new(pattern))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            base.url_patterns.clone()
+        };
+        let formats = if value["formats"].is_array() {
+            get_strings(&value["formats"])
+                .iter()
+                .map(|format| {
+                    ImageFormat::from_wx_fmt(format)
+                        .ok_or_else(|| anyhow::anyhow!("Unknown image format {format}"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        } else {
+            base.formats.clone()
+        };
+        Ok(ImageRules {
+            url_patterns,
+            formats,
+            min_width: value["min_width"].as_u32().unwrap_or(base.min_width),
+            min_height: value["min_height"].as_u32().unwrap_or(base.min_height),
+            max_width: value["max_width"].as_u32().or(base.max_width),
+            max_height: value["max_height"].as_u32().or(base.max_height),
+            min_aspect_ratio: value["min_aspect_ratio"].as_f64().or(base.min_aspect_ratio),
+            max_aspect_ratio: value["max_aspect_ratio"].as_f64().or(base.max_aspect_ratio),
+            region: value["region"]
+                .as_str()
+                .map_or_else(|| base.region.clone(), std::string::ToString::to_string),
+            skip_first: value["skip_first"].as_usize().unwrap_or(base.skip_first),
+            skip_last: value["skip_last"].as_usize().unwrap_or(base.skip_last),
+            min_sheet_score: value["min_sheet_score"]
+                .as_f64()
+                .unwrap_or(base.min_sheet_score),
+            duplicate_distance: value["duplicate_distance"]
+                .as_u32()
+                .unwrap_or(base.duplicate_distance),
+            blocklist_min_articles: value["blocklist_min_articles"]
+                .as_usize()
+                .unwrap_or(base.blocklist_min_articles),
+        })
+    }
+
+    /// Check the URL of an image before downloading it.
+    pub fn matches_url(&self, url: &str, format: Option<ImageFormat>) -> bool {
+        format.is_some_and(|format| self.formats.contains(&format))
+            && self.url_patterns.iter().all(|re| re.is_match(url))
+    }
+
+    /// Check the downloaded image against the format and dimension limits.
+    pub fn matches_image(&self, format: ImageFormat, width: u32, height: u32) -> bool {
+        let ratio = f64::from(height) / f64::from(width.max(1));
+        self.formats.contains(&format)
+            && width >= self.min_width
+            && height >= self.min_height
+            && self.max_width.is_none_or(|max| width <= max)
+            && self.max_height.is_none_or(|max| height <= max)
+            && self.min_aspect_ratio.is_none_or(|min| ratio >= min)
+            && self.max_aspect_ratio.is_none_or(|max| ratio <= max)
+    }
+
+    /// Drop the candidates excluded by their position in the article.
+    pub fn select_position<T>(&self, candidates: Vec<T>) -> Vec<T> {
+        let end = candidates.len().saturating_sub(self.skip_last);
+        candidates
+            .into_iter()
+            .take(end)
+            .skip(self.skip_first)
+            .collect()
+    }
+}
+
+/// Clean-up applied to the sheet pages after downloading them.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone)]
+pub struct PostProcessOptions {
+    pub enabled: bool,
+    /// Trim uniform borders
+    pub crop: bool,
+    /// Rotate the page so that the staff lines are horizontal
+    pub deskew: bool,
+    pub max_skew_degrees: f64,
+    /// Turn the page into clean black and white
+    pub binarize: bool,
+    /// Size in pixels of the neighborhood a pixel is compared with
+    pub threshold_window: u32,
+    /// How much darker than its neighborhood a pixel must be to be ink
+    pub threshold_ratio: f64,
+    /// Pixels lighter than this are never ink, which removes light watermarks
+    pub max_ink_luma: u8,
+}
+
+impl Default for PostProcessOptions {
+    fn default() -> Self {
+        PostProcessOptions {
+            enabled: false,
+            crop: true,
+            deskew: true,
+            max_skew_degrees: 3.0,
+            binarize: true,
+            threshold_window: 41,
+            threshold_ratio: 0.15,
+            max_ink_luma: 160,
+        }
+    }
+}
+
+impl PostProcessOptions {
+    fn from_json(value: &json::JsonValue, base: &PostProcessOptions) -> PostProcessOptions {
+        PostProcessOptions {
+            enabled: value["enabled"].as_bool().unwrap_or(base.enabled),
+            crop: value["crop"].as_bool().unwrap_or(base.crop),
+            deskew: value["deskew"].as_bool().unwrap_or(base.deskew),
+            max_skew_degrees: value["max_skew_degrees"]
+                .as_f64()
+                .unwrap_or(base.max_skew_degrees),
+            binarize: value["binarize"].as_bool().unwrap_or(base.binarize),
+            threshold_window: value["threshold_window"]
+                .as_u32()
+                .unwrap_or(base.threshold_window),
+            threshold_ratio: value["threshold_ratio"]
+                .as_f64()
+                .unwrap_or(base.threshold_ratio),
+            max_ink_luma: value["max_ink_luma"].as_u8().unwrap_or(base.max_ink_luma),
+        }
+    }
+}
+
+/// How images that are not shaped like a page are turned into pages.
+#[derive(Clone)]
+pub struct LayoutOptions {
+    pub enabled: bool,
+    /// Height / width of the printed page
+    pub page_ratio: f64,
+    /// Images taller than this height / width are split into pages
+    pub split_ratio: f64,
+    /// Consecutive images shorter than this height / width are joined into pages
+    pub strip_ratio: f64,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        LayoutOptions {
+            enabled: true,
+            page_ratio: 297.0 / 210.0,
+            split_ratio: 2.0,
+            strip_ratio: 0.75,
+        }
+    }
+}
+
+impl LayoutOptions {
+    fn from_json(value: &json::JsonValue, base: &LayoutOptions) -> LayoutOptions {
+        LayoutOptions {
+            enabled: value["enabled"].as_bool().unwrap_or(base.enabled),
+            page_ratio: value["page_ratio"].as_f64().unwrap_or(base.page_ratio),
+            split_ratio: value["split_ratio"].as_f64().unwrap_or(base.split_ratio),
+            strip_ratio: value["strip_ratio"].as_f64().unwrap_or(base.strip_ratio),
+        }
+    }
+}
+
+/// How the `|`-separated parts of an article title make the song name.
+#[derive(Clone)]
+pub struct TitleOptions {
+    /// Names of the parts in the order they appear in the title
+    pub parts: Vec<String>,
+    /// Segments such as `{artist}` joined with the separator. A segment that
+    /// uses a missing part is left out.
+    pub template: Vec<String>,
+    pub separator: String,
+}
+
+impl Default for TitleOptions {
+    fn default() -> Self {
+        TitleOptions {
+            parts: vec!["song".to_string(), "artist".to_string()],
+            template: vec!["{artist}".to_string(), "{song}".to_string()],
+            separator: " - ".to_string(),
+        }
+    }
+}
+
+impl TitleOptions {
+    fn from_json(value: &json::JsonValue, base: &TitleOptions) -> TitleOptions {
+        let get_list = |key: &str, base: &Vec<String>| {
+            if value[key].is_array() {
+                get_strings(&value[key])
+            } else {
+                base.clone()
+            }
+        };
+        TitleOptions {
+            parts: get_list("parts", &base.parts),
+            template: get_list("template", &base.template),
+            separator: value["separator"]
+                .as_str()
+                .map_or_else(|| base.separator.clone(), std::string::ToString::to_string),
+        }
+    }
+}
+
+/// Settings that can differ between Weixin accounts.
+#[derive(Clone)]
+pub struct Profile {
+    pub name: String,
+    /// Account nicknames that use this profile
+    pub accounts: Vec<String>,
+    pub title: TitleOptions,
+    pub image: ImageRules,
+    pub layout: LayoutOptions,
+    pub postprocess: PostProcessOptions,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            name: DEFAULT_PROFILE.to_string(),
+            accounts: Vec::new(),
+            title: TitleOptions::default(),
+            image: ImageRules::default(),
+            layout: LayoutOptions::default(),
+            postprocess: PostProcessOptions::default(),
+        }
+    }
+}
+
+impl Profile {
+    fn from_json(name: &str, value: &json::JsonValue, base: &Profile) -> anyhow::Result<Profile> {
+        Ok(Profile {
+            name: name.to_string(),
+            accounts: get_strings(&value["accounts"]),
+            title: TitleOptions::from_json(&value["title"], &base.title),
+            image: ImageRules::from_json(&value["image"], &base.image)?,
+            layout: LayoutOptions::from_json(&value["layout"], &base.layout),
+            postprocess: PostProcessOptions::from_json(&value["postprocess"], &base.postprocess),
+        })
+    }
+}
+
+/// How the sheet pages are assembled into a PDF.
+#[derive(Clone)]
+pub struct PdfOptions {
+    pub enabled: bool,
+    /// Name template of the PDF inside the song folder
+    pub file_name: String,
+    pub page_size: PageSize,
+    pub margin_mm: f32,
+    /// Scale small images up to fill the page instead of only shrinking large ones
+    pub fit_to_page: bool,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        PdfOptions {
+            enabled: true,
+            file_name: "{sheet}.pdf".to_string(),
+            page_size: PageSize::A4,
+            margin_mm: 10.0,
+            fit_to_page: true,
+        }
+    }
+}
+
+impl PdfOptions {
+    fn from_json(value: &json::JsonValue) -> anyhow::Result<PdfOptions> {
+        let default = PdfOptions::default();
+        let page_size = match value["page_size"].as_str() {
+            Some(name) => PageSize::from_name(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown page size {name}"))?,
+            None => default.page_size,
+        };
+        Ok(PdfOptions {
+            enabled: value["enabled"].as_bool().unwrap_or(default.enabled),
+            file_name: value["file_name"]
+                .as_str()
+                .map_or(default.file_name, std::string::ToString::to_string),
+            page_size,
+            margin_mm: value["margin_mm"].as_f32().unwrap_or(default.margin_mm),
+            fit_to_page: value["fit_to_page"]
+                .as_bool()
+                .unwrap_or(default.fit_to_page),
+        })
+    }
+}
+
+/// Where the files of a song are saved and how they are named.
+#[derive(Clone)]
+pub struct OutputOptions {
+    /// Path of the song folder under `output/`, such as `{account}/{date:%Y}/{title}`
+    pub folder: String,
+    /// Join the folders of the path into a single folder name
+    pub flat: bool,
+    /// Language of the fixed names such as `{accompaniment}`: `zh`, `zh-Hant` or `en`
+    pub language: String,
+    pub page: String,
+    pub accompaniment: String,
+    pub audio: String,
+    pub video: String,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            folder: "{title}".to_string(),
+            flat: false,
+            language: "zh".to_string(),
+            page: "{page}".to_string(),
+            accompaniment: "{accompaniment}".to_string(),
+            audio: "{name}".to_string(),
+            video: "{title}".to_string(),
+        }
+    }
+}
+
+impl OutputOptions {
+    fn from_json(value: &json::JsonValue) -> OutputOptions {
+        let default = OutputOptions::default();
+        let get_string = |key: &str, default: String| {
+            value[key]
+                .as_str()
+                .map_or(default, std::string::ToString::to_string)
+        };
+        OutputOptions {
+            folder: get_string("folder", default.folder),
+            flat: value["flat"].as_bool().unwrap_or(default.flat),
+            language: get_string("language", default.language),
+            page: get_string("page", default.page),
+            accompaniment: get_string("accompaniment", default.accompaniment),
+            audio: get_string("audio", default.audio),
+            video: get_string("video", default.video),
+        }
+    }
+}
+
+/// How a local optical music recognition engine turns the sheet pages into `MusicXML`.
+#[derive(Clone)]
+pub struct OmrOptions {
+    pub enabled: bool,
+    pub command: String,
+    /// Arguments of the command, where `{input}` is the page image and
+    /// `{output}` the folder to write into
+    pub args: Vec<String>,
+    /// Folder of the song that receives the `MusicXML` files
+    pub folder: String,
+}
+
+impl Default for OmrOptions {
+    fn default() -> Self {
+        OmrOptions {
+            enabled: false,
+            command: "audiveris".to_string(),
+            args: ["-batch", "-export", "-output", "{output}", "{input}"]
+                .into_iter()
+                .map(std::string::ToString::to_string)
+                .collect(),
+            folder: "musicxml".to_string(),
+        }
+    }
+}
+
+impl OmrOptions {
+    fn from_json(value: &json::JsonValue) -> OmrOptions {
+        let default = OmrOptions::default();
+        OmrOptions {
+            enabled: value["enabled"].as_bool().unwrap_or(default.enabled),
+            command: value["command"]
+                .as_str()
+                .map_or(default.command, std::string::ToString::to_string),
+            args: if value["args"].is_array() {
+                get_strings(&value["args"])
+            } else {
+                default.args
+            },
+            folder: value["folder"]
+                .as_str()
+                .map_or(default.folder, std::string::ToString::to_string),
+        }
+    }
+}
+
+/// Access to the job API of the web server.
+#[derive(Clone, Default)]
+pub struct ServerOptions {
+    /// Token that clients send as `Authorization: Bearer <token>`, the job API
+    /// is disabled without one
+    pub token: Option<String>,
+}
+
+impl ServerOptions {
+    fn from_json(value: &json::JsonValue) -> ServerOptions {
+        ServerOptions {
+            token: value["token"]
+                .as_str()
+                .filter(|token| !token.is_empty())
+                .map(std::string::ToString::to_string),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub default: Profile,
+    pub profiles: Vec<Profile>,
+    pub output: OutputOptions,
+    pub pdf: PdfOptions,
+    pub omr: OmrOptions,
+    pub server: ServerOptions,
+}
+
+fn get_strings(value: &json::JsonValue) -> Vec<String> {
+    value
+        .members()
+        .filter_map(json::JsonValue::as_str)
+        .map(std::string::ToString::to_string)
+        .collect()
+}
+
+impl Config {
+    /// Load the config file, or use the built-in defaults if it doesn't exist.
+    pub fn load(path: &str) -> anyhow::Result<Config> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::info!("No {path} found, using the default config");
+                return Ok(Config::default());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let value = json::parse(&content)?;
+
+        // Every other profile inherits what it doesn't set from the default one
+        let default = Profile::from_json(
+            DEFAULT_PROFILE,
+            &value["profiles"][DEFAULT_PROFILE],
+            &Profile::default(),
+        )?;
+        let profiles = value["profiles"]
+            .entries()
+            .filter(|(name, _)| *name != DEFAULT_PROFILE)
+            .map(|(name, profile)| Profile::from_json(name, profile, &default))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let output = OutputOptions::from_json(&value["output"]);
+        let pdf = PdfOptions::from_json(&value["pdf"])?;
+        let omr = OmrOptions::from_json(&value["omr"]);
+        let server = ServerOptions::from_json(&value["server"]);
+        Ok(Config {
+            default,
+            profiles,
+            output,
+            pdf,
+            omr,
+            server,
+        })
+    }
+
+    /// Find the profile configured for the account, falling back to the default one.
+    pub fn get_profile(&self, account: Option<&str>) -> &Profile {
+        account
+            .and_then(|account| {
+                self.profiles
+                    .iter()
+                    .find(|profile| profile.accounts.iter().any(|a| a == account))
+            })
+            .unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_url_rules() {
+        let rules = ImageRules::default();
+        let url = "https://mmbiz.qpic.cn/mmbiz_png/abc/640?wx_fmt=png&from=appmsg#imgIndex=2";
+        assert!(rules.matches_url(url, Some(ImageFormat::Png)));
+        assert!(!rules.matches_url(url, None));
+        let other_host = "https://example.com/a.png?from=appmsg#imgIndex=2";
+        assert!(!rules.matches_url(other_host, Some(ImageFormat::Png)));
+        let no_index = "https://mmbiz.qpic.cn/mmbiz_png/abc/640?wx_fmt=png&from=appmsg";
+        assert!(!rules.matches_url(no_index, Some(ImageFormat::Png)));
+
+        let png_only = ImageRules {
+            formats: vec![ImageFormat::Png],
+            ..ImageRules::default()
+        };
+        assert!(!png_only.matches_url(url, Some(ImageFormat::Gif)));
+    }
+}
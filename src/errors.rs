@@ -5,4 +5,7 @@ pub enum SheetError {
 
     #[error("Parse Failed")]
     ParseFailed,
+
+    #[error("Invalid audio: {0}")]
+    InvalidAudio(String),
 }
@@ -0,0 +1,248 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl ImageFormat {
+    /// Guess the format from the magic bytes of the content.
+    pub fn detect(binary: &[u8]) -> Option<ImageFormat> {
+        if binary.starts_with(&[137, 80, 78, 71, 13, 10, 26, 10]) {
+            Some(ImageFormat::Png)
+        } else if binary.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::Jpeg)
+        } else if binary.starts_with(b"GIF87a") || binary.starts_with(b"GIF89a") {
+            Some(ImageFormat::Gif)
+        } else if binary.len() >= 12 && &binary[..4] == b"RIFF" && &binary[8..12] == b"WEBP" {
+            Some(ImageFormat::Webp)
+        } else {
+            None
+        }
+    }
+
+    /// Parse the value of the `wx_fmt` URL parameter used by mmbiz.
+    pub fn from_wx_fmt(wx_fmt: &str) -> Option<ImageFormat> {
+        match wx_fmt.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "gif" => Some(ImageFormat::Gif),
+            "webp" => Some(ImageFormat::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Webp => "webp",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+}
+
+fn read_u16_be(binary: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from(u16::from_be_bytes(
+        binary.get(offset..offset + 2)?.try_into().ok()?,
+    )))
+}
+
+fn read_u16_le(binary: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from(u16::from_le_bytes(
+        binary.get(offset..offset + 2)?.try_into().ok()?,
+    )))
+}
+
+fn read_u24_le(binary: &[u8], offset: usize) -> Option<u32> {
+    let bytes = binary.get(offset..offset + 3)?;
+    Some(u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16)
+}
+
+fn get_png_dimensions(binary: &[u8]) -> Option<(u32, u32)> {
+    // The IHDR chunk always comes first
+    let width = u32::from_be_bytes(binary.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(binary.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
+
+fn get_jpeg_dimensions(binary: &[u8]) -> Option<(u32, u32)> {
+    // Walk the marker segments until a start-of-frame marker
+    let mut offset = 2;
+    loop {
+        if *binary.get(offset)? != 0xFF {
+            return None;
+        }
+        let marker = *binary.get(offset + 1)?;
+        match marker {
+            // Fill byte
+            0xFF => offset += 1,
+            // Standalone markers without a length
+            0x01 | 0xD0..=0xD7 => offset += 2,
+            // SOF0-SOF15, except DHT (C4), JPG (C8) and DAC (CC)
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let height = read_u16_be(binary, offset + 5)?;
+                let width = read_u16_be(binary, offset + 7)?;
+                return Some((width, height));
+            }
+            // End of image or start of scan before any frame header
+            0xD9 | 0xDA => return None,
+            _ => {
+                let length = usize::try_from(read_u16_be(binary, offset + 2)?).ok()?;
+                offset += 2 + length;
+            }
+        }
+    }
+}
+
+fn get_gif_dimensions(binary: &[u8]) -> Option<(u32, u32)> {
+    Some((read_u16_le(binary, 6)?, read_u16_le(binary, 8)?))
+}
+
+fn get_webp_dimensions(binary: &[u8]) -> Option<(u32, u32)> {
+    match binary.get(12..16)? {
+        // Lossy: frame tag, start code 9D 01 2A, then 14-bit width and height
+        b"VP8 " => {
+            if binary.get(23..26)? != [0x9D, 0x01, 0x2A] {
+                return None;
+            }
+            let width = read_u16_le(binary, 26)? & 0x3FFF;
+            let height = read_u16_le(binary, 28)? & 0x3FFF;
+            Some((width, height))
+        }
+        // Lossless: signature 2F, then 14-bit width-1 and height-1
+        b"VP8L" => {
+            if *binary.get(20)? != 0x2F {
+                return None;
+            }
+            let bits = u32::from_le_bytes(binary.get(21..25)?.try_into().ok()?);
+            let width = (bits & 0x3FFF) + 1;
+            let height = ((bits >> 14) & 0x3FFF) + 1;
+            Some((width, height))
+        }
+        // Extended: 24-bit canvas width-1 and height-1
+        b"VP8X" => {
+            let width = read_u24_le(binary, 24)? + 1;
+            let height = read_u24_le(binary, 27)? + 1;
+            Some((width, height))
+        }
+        _ => None,
+    }
+}
+
+/// Detect the image format and read its dimensions from the header only.
+pub fn get_dimensions(binary: &[u8]) -> Option<(ImageFormat, u32, u32)> {
+    let format = ImageFormat::detect(binary)?;
+    let (width, height) = match format {
+        ImageFormat::Png => get_png_dimensions(binary),
+        ImageFormat::Jpeg => get_jpeg_dimensions(binary),
+        ImageFormat::Gif => get_gif_dimensions(binary),
+        ImageFormat::Webp => get_webp_dimensions(binary),
+    }?;
+    Some((format, width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webp(chunk: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut binary = b"RIFF\0\0\0\0WEBP".to_vec();
+        binary.extend(chunk);
+        binary.extend([0; 4]);
+        binary.extend(payload);
+        binary
+    }
+
+    #[test]
+    fn png_header() {
+        let mut binary = vec![137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13];
+        binary.extend(b"IHDR");
+        binary.extend(800u32.to_be_bytes());
+        binary.extend(1131u32.to_be_bytes());
+        assert_eq!(get_dimensions(&binary), Some((ImageFormat::Png, 800, 1131)));
+    }
+
+    #[test]
+    fn jpeg_start_of_frame() {
+        let mut binary = vec![0xFF, 0xD8];
+        // APP0 segment, then a fill byte before a progressive SOF2
+        binary.extend([0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46]);
+        binary.extend([0xFF, 0xFF, 0xC2, 0x00, 0x11, 0x08, 0x04, 0x6B, 0x03, 0x20]);
+        assert_eq!(
+            get_dimensions(&binary),
+            Some((ImageFormat::Jpeg, 800, 1131))
+        );
+    }
+
+    #[test]
+    fn jpeg_skips_tables_and_stops_at_scan() {
+        // DHT (C4) has a length and is not a frame header
+        let mut binary = vec![0xFF, 0xD8, 0xFF, 0xC4, 0x00, 0x03, 0x00];
+        binary.extend([0xFF, 0xC0, 0x00, 0x11, 0x08, 0x00, 0x10, 0x00, 0x20]);
+        assert_eq!(get_dimensions(&binary), Some((ImageFormat::Jpeg, 32, 16)));
+        let scan_first = [0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xC0];
+        assert_eq!(get_dimensions(&scan_first), None);
+        assert_eq!(get_dimensions(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00]), None);
+    }
+
+    #[test]
+    fn gif_screen_descriptor() {
+        let mut binary = b"GIF89a".to_vec();
+        binary.extend([0x20, 0x03, 0x6B, 0x04]);
+        assert_eq!(get_dimensions(&binary), Some((ImageFormat::Gif, 800, 1131)));
+        assert_eq!(get_dimensions(b"GIF87a\x20"), None);
+    }
+
+    #[test]
+    fn webp_lossy() {
+        let payload = [0, 0, 0, 0x9D, 0x01, 0x2A, 0x20, 0x43, 0x6B, 0x84];
+        // The top two bits of each dimension are the scale
+        assert_eq!(
+            get_dimensions(&webp(b"VP8 ", &payload)),
+            Some((ImageFormat::Webp, 800, 1131))
+        );
+        let bad_start = [0, 0, 0, 0x9D, 0x01, 0x2B, 0x20, 0x03, 0x6B, 0x04];
+        assert_eq!(get_dimensions(&webp(b"VP8 ", &bad_start)), None);
+    }
+
+    #[test]
+    fn webp_lossless() {
+        // 800 - 1 and 1131 - 1
+        let bits: u32 = 0x31F | 0x46A << 14;
+        let mut payload = vec![0x2F];
+        payload.extend(bits.to_le_bytes());
+        assert_eq!(
+            get_dimensions(&webp(b"VP8L", &payload)),
+            Some((ImageFormat::Webp, 800, 1131))
+        );
+        payload[0] = 0x2E;
+        assert_eq!(get_dimensions(&webp(b"VP8L", &payload)), None);
+    }
+
+    #[test]
+    fn webp_extended() {
+        let payload = [0x10, 0, 0, 0, 0x1F, 0x03, 0x00, 0x6A, 0x04, 0x00];
+        assert_eq!(
+            get_dimensions(&webp(b"VP8X", &payload)),
+            Some((ImageFormat::Webp, 800, 1131))
+        );
+        assert_eq!(get_dimensions(&webp(b"ALPH", &payload)), None);
+    }
+
+    #[test]
+    fn unknown_content() {
+        assert_eq!(get_dimensions(b"<html>"), None);
+        assert_eq!(ImageFormat::detect(b"RIFF\0\0\0\0WAVE"), None);
+    }
+}
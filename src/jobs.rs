@@ -0,0 +1,352 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use crate::{config, sheet};
+
+// Don't access the website too fast, as `download` does between articles
+const JOB_DELAY_SECS: u64 = 5;
+// Finished jobs are forgotten after a day, or earlier if there are too many
+const JOB_RETENTION_SECS: i64 = 24 * 60 * 60;
+const MAX_FINISHED_JOBS: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum State {
+    Queued,
+    Running,
+    Done,
+    Skipped,
+    Failed,
+}
+
+impl State {
+    fn as_str(self) -> &'static str {
+        match self {
+            State::Queued => "queued",
+            State::Running => "running",
+            State::Done => "done",
+            State::Skipped => "skipped",
+            State::Failed => "failed",
+        }
+    }
+}
+
+/// An audio track, an image candidate, the PDF or the video of a job.
+pub struct Asset {
+    pub kind: String,
+    /// The URL of a download or the name of a file that is made
+    pub name: String,
+    /// The file written into the song folder
+    pub file: Option<String>,
+    pub state: State,
+    pub error: Option<String>,
+}
+
+/// An article submitted to the download pipeline.
+pub struct Job {
+    pub id: u64,
+    pub url: String,
+    pub video_url: Option<String>,
+    pub state: State,
+    /// The step of the pipeline that is running
+    pub stage: Option<String>,
+    /// Path of the song folder relative to the output folder
+    pub folder: Option<String>,
+    pub assets: Vec<Asset>,
+    pub error: Option<String>,
+    pub created_time: i64,
+    pub finished_time: Option<i64>,
+}
+
+impl Job {
+    pub fn to_json(&self) -> json::JsonValue {
+        let assets = self
+            .assets
+            .iter()
+            .map(|asset| {
+                json::object! {
+                    kind: asset.kind.clone(),
+                    name: asset.name.clone(),
+                    file: asset.file.clone(),
+                    state: asset.state.as_str(),
+                    error: asset.error.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+        json::object! {
+            id: self.id,
+            url: self.url.clone(),
+            video_url: self.video_url.clone(),
+            state: self.state.as_str(),
+            stage: self.stage.clone(),
+            folder: self.folder.clone(),
+            assets: assets,
+            error: self.error.clone(),
+            created_time: self.created_time,
+            finished_time: self.finished_time,
+        }
+    }
+}
+
+/// Where the pipeline reports its progress on a job. The default one is not
+/// attached to a job and ignores the reports.
+#[derive(Clone, Default)]
+pub struct Progress {
+    job: Option<Arc<Mutex<Job>>>,
+    output: String,
+}
+
+impl Progress {
+    fn update(&self, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = &self.job {
+            if let Ok(mut job) = job.lock() {
+                f(&mut job);
+            }
+        }
+    }
+
+    pub fn stage(&self, stage: &str) {
+        self.update(|job| job.stage = Some(stage.to_string()));
+    }
+
+    pub fn folder(&self, path: &str) {
+        let folder = std::path::Path::new(path)
+            .strip_prefix(&self.output)
+            .unwrap_or(std::path::Path::new(path))
+            .to_string_lossy()
+            .to_string();
+        self.update(|job| job.folder = Some(folder));
+    }
+
+    /// Change the asset of that kind and name, adding it first if it is new.
+    fn update_asset(&self, kind: &str, name: &str, f: impl FnOnce(&mut Asset)) {
+        self.update(|job| {
+            let idx = job
+                .assets
+                .iter()
+                .position(|asset| asset.kind == kind && asset.name == name)
+                .unwrap_or_else(|| {
+                    job.assets.push(Asset {
+                        kind: kind.to_string(),
+                        name: name.to_string(),
+                        file: None,
+                        state: State::Queued,
+                        error: None,
+                    });
+                    job.assets.len() - 1
+                });
+            f(&mut job.assets[idx]);
+        });
+    }
+
+    pub fn asset(&self, kind: &str, name: &str, state: State) {
+        self.update_asset(kind, name, |asset| asset.state = state);
+    }
+
+    pub fn asset_done(&self, kind: &str, name: &str, file: &str) {
+        self.update_asset(kind, name, |asset| {
+            asset.state = State::Done;
+            asset.file = Some(file.to_string());
+        });
+    }
+
+    pub fn asset_failed(&self, kind: &str, name: &str, error: &anyhow::Error) {
+        self.update_asset(kind, name, |asset| {
+            asset.state = State::Failed;
+            asset.error = Some(error.to_string());
+        });
+    }
+}
+
+/// The jobs submitted through the API, run one at a time in a worker thread.
+pub struct Queue {
+    jobs: Mutex<Vec<Arc<Mutex<Job>>>>,
+    next_id: AtomicU64,
+    sender: tokio::sync::mpsc::UnboundedSender<Progress>,
+    output: String,
+}
+
+impl Queue {
+    /// Start the worker, which runs the jobs with the config like `download`.
+    pub fn start(config: config::Config, output: &str) -> anyhow::Result<Arc<Queue>> {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<Progress>();
+        // The pipeline blocks at times, so it gets a runtime of its own
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let worker_output = output.to_string();
+        std::thread::spawn(move || {
+            runtime.block_on(async {
+                while let Some(progress) = receiver.recv().await {
+                    run(&config, &worker_output, &progress).await;
+                }
+            });
+        });
+        Ok(Arc::new(Queue {
+            jobs: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+            sender,
+            output: output.to_string(),
+        }))
+    }
+
+    /// Add a job and return its description.
+    pub fn submit(
+        &self,
+        url: String,
+        video_url: Option<String>,
+    ) -> anyhow::Result<json::JsonValue> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .map_err(|_| anyhow::anyhow!("The job list is poisoned"))?;
+        let now = chrono::Local::now().timestamp();
+        evict_finished(&mut jobs, now);
+        let job = Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            url,
+            video_url,
+            state: State::Queued,
+            stage: None,
+            folder: None,
+            assets: Vec::new(),
+            error: None,
+            created_time: now,
+            finished_time: None,
+        };
+        let value = job.to_json();
+        let job = Arc::new(Mutex::new(job));
+        jobs.push(job.clone());
+        self.sender.send(Progress {
+            job: Some(job),
+            output: self.output.clone(),
+        })?;
+        Ok(value)
+    }
+
+    pub fn list(&self) -> Vec<json::JsonValue> {
+        let Ok(jobs) = self.jobs.lock() else {
+            return Vec::new();
+        };
+        jobs.iter()
+            .filter_map(|job| Some(job.lock().ok()?.to_json()))
+            .collect()
+    }
+
+    /// The description of a job and the folder of its song.
+    pub fn get(&self, id: u64) -> Option<(json::JsonValue, Option<String>)> {
+        let jobs = self.jobs.lock().ok()?;
+        jobs.iter().find_map(|job| {
+            let job = job.lock().ok()?;
+            (job.id == id).then(|| (job.to_json(), job.folder.clone()))
+        })
+    }
+}
+
+/// Forget the jobs that finished before the retention period, and the oldest
+/// finished jobs beyond `MAX_FINISHED_JOBS`. Queued and running jobs stay.
+fn evict_finished(jobs: &mut Vec<Arc<Mutex<Job>>>, now: i64) {
+    let finished_time = |job: &Arc<Mutex<Job>>| job.lock().ok().and_then(|job| job.finished_time);
+    jobs.retain(|job| finished_time(job).is_none_or(|time| now - time < JOB_RETENTION_SECS));
+    let mut extra = jobs
+        .iter()
+        .filter(|job| finished_time(job).is_some())
+        .count()
+        .saturating_sub(MAX_FINISHED_JOBS);
+    // The jobs are in the order of submission, so the oldest come first
+    jobs.retain(|job| {
+        if extra > 0 && finished_time(job).is_some() {
+            extra -= 1;
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Run the pipeline of `download` on the article of a job.
+async fn run(config: &config::Config, output: &str, progress: &Progress) {
+    let mut request = None;
+    progress.update(|job| {
+        job.state = State::Running;
+        request = Some((job.url.clone(), job.video_url.clone()));
+    });
+    let Some((url, video_url)) = request else {
+        return;
+    };
+    let video_optional = video_url.is_none();
+    tokio::time::sleep(std::time::Duration::from_secs(JOB_DELAY_SECS)).await;
+
+    progress.stage("parse");
+    let result = match sheet::Sheet::try_new(url.clone(), video_url, config).await {
+        Ok(mut sheet) => {
+            sheet.set_progress(progress.clone());
+            if video_optional {
+                sheet.set_video_optional();
+            }
+            sheet.download(output).await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &result {
+        log::error!("Failed to download {url}: {e:?}");
+    }
+    progress.update(|job| {
+        job.state = if result.is_ok() {
+            State::Done
+        } else {
+            State::Failed
+        };
+        job.error = result.err().map(|e| e.to_string());
+        job.finished_time = Some(chrono::Local::now().timestamp());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::numeric::to_u64;
+
+    fn job(id: u64, finished_time: Option<i64>) -> Arc<Mutex<Job>> {
+        Arc::new(Mutex::new(Job {
+            id,
+            url: String::new(),
+            video_url: None,
+            state: if finished_time.is_some() {
+                State::Done
+            } else {
+                State::Queued
+            },
+            stage: None,
+            folder: None,
+            assets: Vec::new(),
+            error: None,
+            created_time: 0,
+            finished_time,
+        }))
+    }
+
+    fn ids(jobs: &[Arc<Mutex<Job>>]) -> Vec<u64> {
+        jobs.iter().map(|job| job.lock().unwrap().id).collect()
+    }
+
+    #[test]
+    fn forget_old_jobs() {
+        let now = JOB_RETENTION_SECS * 2;
+        let mut jobs = vec![job(1, Some(0)), job(2, None), job(3, Some(now - 10))];
+        evict_finished(&mut jobs, now);
+        assert_eq!(ids(&jobs), [2, 3]);
+    }
+
+    #[test]
+    fn cap_finished_jobs() {
+        let mut jobs = (1..=MAX_FINISHED_JOBS + 2)
+            .map(|id| job(to_u64(id), Some(0)))
+            .collect::<Vec<_>>();
+        jobs.insert(1, job(0, None));
+        evict_finished(&mut jobs, 0);
+        assert_eq!(jobs.len(), MAX_FINISHED_JOBS + 1);
+        assert_eq!(ids(&jobs)[..3], [0, 3, 4]);
+    }
+}
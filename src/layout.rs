@@ -0,0 +1,280 @@
+use image::{GenericImage, RgbImage};
+
+use crate::{config::LayoutOptions, numeric::to_u32};
+
+// Pixels darker than this count as ink when looking for gaps
+const INK_LUMA: u8 = 160;
+// Strips joined together must have the same width within this ratio
+const WIDTH_TOLERANCE: f64 = 0.02;
+
+/// A page after normalization, with the indexes of the images it comes from.
+pub struct Page {
+    /// The new image, or `None` for an image that is already a page
+    pub image: Option<RgbImage>,
+    pub sources: Vec<usize>,
+}
+
+fn ratio(image: &RgbImage) -> f64 {
+    f64::from(image.height()) / f64::from(image.width().max(1))
+}
+
+/// Runs of rows without ink, as (first row, last row).
+fn find_gaps(image: &RgbImage) -> Vec<(u32, u32)> {
+    let mut gaps = Vec::new();
+    let mut start = None;
+    for (y, row) in (0u32..).zip(image.rows()) {
+        let blank = row
+            .into_iter()
+            .all(|p| p.0.iter().map(|c| u32::from(*c)).sum::<u32>() / 3 >= u32::from(INK_LUMA));
+        match (blank, start) {
+            (true, None) => start = Some(y),
+            (false, Some(first)) => {
+                gaps.push((first, y - 1));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = start {
+        gaps.push((first, image.height() - 1));
+    }
+    gaps
+}
+
+/// Cut a tall image into page-shaped pieces, preferring the gaps between staff systems.
+fn split(image: &RgbImage, options: &LayoutOptions) -> Vec<RgbImage> {
+    let max_height = to_u32(f64::from(image.width()) * options.page_ratio).max(1);
+    let gaps = find_gaps(image);
+    let mut pieces = Vec::new();
+    let mut top = 0;
+    while image.height() - top > max_height {
+        let limit = top + max_height;
+        // Cut in the middle of the lowest gap that still fits on the page
+        let cut = gaps
+            .iter()
+            .map(|(first, last)| (first + last) / 2)
+            .filter(|middle| *middle > top + max_height / 3 && *middle <= limit)
+            .max()
+            .unwrap_or(limit);
+        pieces.push(image::imageops::crop_imm(image, 0, top, image.width(), cut - top).to_image());
+        top = cut;
+    }
+    pieces.push(
+        image::imageops::crop_imm(image, 0, top, image.width(), image.height() - top).to_image(),
+    );
+    pieces
+}
+
+/// Stack strips of the same width on top of each other.
+fn join(strips: &[RgbImage]) -> anyhow::Result<RgbImage> {
+    let width = strips.iter().map(RgbImage::width).max().unwrap_or(1);
+    let height = strips.iter().map(RgbImage::height).sum();
+    let mut page = RgbImage::from_pixel(width, height, image::Rgb([255, 255, 255]));
+    let mut top = 0;
+    for strip in strips {
+        page.copy_from(strip, 0, top)?;
+        top += strip.height();
+    }
+    Ok(page)
+}
+
+/// Split very tall images and join consecutive narrow strips into page-shaped
+/// images. Returns `None` if every image is already a page.
+pub fn normalize(
+    images: &[RgbImage],
+    options: &LayoutOptions,
+) -> anyhow::Result<Option<Vec<Page>>> {
+    let is_tall = |image: &RgbImage| ratio(image) > options.split_ratio;
+    let is_strip = |image: &RgbImage| ratio(image) < options.strip_ratio;
+    let same_width = |a: &RgbImage, b: &RgbImage| {
+        f64::from(a.width().abs_diff(b.width())) <= f64::from(a.width()) * WIDTH_TOLERANCE
+    };
+    let has_strips = images
+        .windows(2)
+        .any(|pair| is_strip(&pair[0]) && is_strip(&pair[1]) && same_width(&pair[0], &pair[1]));
+    if !has_strips && !images.iter().any(is_tall) {
+        return Ok(None);
+    }
+
+    let mut pages = Vec::new();
+    let mut idx = 0;
+    while idx < images.len() {
+        let image = &images[idx];
+        if is_tall(image) {
+            log::info!("Splitting tall image {}", idx + 1);
+            pages.extend(split(image, options).into_iter().map(|image| Page {
+                image: Some(image),
+                sources: vec![idx],
+            }));
+            idx += 1;
+        } else if is_strip(image) {
+            // Take the following strips of the same width while they fit on the page
+            let max_height = f64::from(image.width()) * options.page_ratio;
+            let mut end = idx + 1;
+            let mut height = f64::from(image.height());
+            while let Some(next) = images.get(end) {
+                if !is_strip(next)
+                    || !same_width(image, next)
+                    || height + f64::from(next.height()) > max_height
+                {
+                    break;
+                }
+                height += f64::from(next.height());
+                end += 1;
+            }
+            let image = if end - idx > 1 {
+                log::info!("Joining strips {} to {}", idx + 1, end);
+                Some(join(&images[idx..end])?)
+            } else {
+                None
+            };
+            pages.push(Page {
+                image,
+                sources: (idx..end).collect(),
+            });
+            idx = end;
+        } else {
+            pages.push(Page {
+                image: None,
+                sources: vec![idx],
+            });
+            idx += 1;
+        }
+    }
+    Ok(Some(pages))
+}
+
+/// Name the pages in order, numbered from 1 with `name(number, extension)`.
+/// New pages are PNG and the others keep the extension of their downloaded
+/// file in `files`. Fails if two pages would share a stem, as later steps
+/// write `<stem>.png` files.
+pub fn file_names(
+    pages: &[Page],
+    files: &[String],
+    name: impl Fn(usize, &str) -> String,
+) -> anyhow::Result<Vec<String>> {
+    let mut stems = std::collections::HashSet::new();
+    let mut names = Vec::new();
+    for (idx, page) in pages.iter().enumerate() {
+        let extension = match page.image {
+            Some(_) => "png",
+            None => files[page.sources[0]]
+                .rsplit_once('.')
+                .map_or("", |(_, extension)| extension),
+        };
+        let file_name = name(idx + 1, extension);
+        let stem = file_name
+            .rsplit_once('.')
+            .map_or(file_name.as_str(), |(stem, _)| stem);
+        if !stems.insert(stem.to_string()) {
+            anyhow::bail!(
+                "Pages {file_name} would overwrite each other, the page template must number them"
+            );
+        }
+        names.push(file_name);
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_pixel(width, height, image::Rgb([255, 255, 255]))
+    }
+
+    /// A tall image with a staff system of ink every 40 rows.
+    fn tall(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |_, y| {
+            if y % 40 < 20 {
+                image::Rgb([0, 0, 0])
+            } else {
+                image::Rgb([255, 255, 255])
+            }
+        })
+    }
+
+    fn name(number: usize, extension: &str) -> String {
+        format!("{number:02}.{extension}")
+    }
+
+    #[test]
+    fn pages_are_left_alone() {
+        let images = [blank(100, 141), blank(100, 150)];
+        assert!(normalize(&images, &LayoutOptions::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn split_tall_image() {
+        let images = [tall(100, 400)];
+        let pages = normalize(&images, &LayoutOptions::default())
+            .unwrap()
+            .unwrap();
+        assert!(pages.len() >= 3);
+        assert!(pages.iter().all(|page| page.sources == [0]));
+        let heights = pages
+            .iter()
+            .map(|page| page.image.as_ref().unwrap().height())
+            .collect::<Vec<_>>();
+        assert!(heights.iter().all(|height| *height <= 142));
+        assert_eq!(heights.iter().sum::<u32>(), 400);
+    }
+
+    #[test]
+    fn stitch_strips() {
+        let images = [
+            blank(100, 30),
+            blank(100, 30),
+            blank(100, 30),
+            blank(100, 141),
+        ];
+        let pages = normalize(&images, &LayoutOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].sources, [0, 1, 2]);
+        assert_eq!(pages[0].image.as_ref().unwrap().height(), 90);
+        assert_eq!(pages[1].sources, [3]);
+        assert!(pages[1].image.is_none());
+    }
+
+    #[test]
+    fn name_mixed_formats_in_order() {
+        let images = [blank(100, 141), tall(100, 300), blank(100, 141)];
+        let files = ["01.png", "02.png", "03.jpg"].map(String::from);
+        let pages = normalize(&images, &LayoutOptions::default())
+            .unwrap()
+            .unwrap();
+        let names = file_names(&pages, &files, name).unwrap();
+        let last = pages.len();
+        assert_eq!(names.len(), last);
+        assert_eq!(names[0], "01.png");
+        assert_eq!(names[1], "02.png");
+        assert_eq!(names[last - 1], format!("{last:02}.jpg"));
+        // A kept JPEG renumbered after a split must not reuse the stem of a new page
+        let stems = names
+            .iter()
+            .map(|name| name.split('.').next().unwrap())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(stems.len(), last);
+    }
+
+    #[test]
+    fn reject_names_sharing_a_stem() {
+        let pages = [
+            Page {
+                image: None,
+                sources: vec![0],
+            },
+            Page {
+                image: Some(blank(10, 10)),
+                sources: vec![1],
+            },
+        ];
+        let files = ["page.jpg", "page.png"].map(String::from);
+        assert!(file_names(&pages, &files, |_, extension| format!("page.{extension}")).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::LazyLock;
+
+pub const LYRICS_FILE: &str = "lyrics.txt";
+
+// Lines longer than this are prose rather than lyrics
+const MAX_LYRIC_CHARS: usize = 40;
+// Fewer lyric lines than this are not worth a lyrics file
+const MIN_LYRIC_LINES: usize = 4;
+// Lines with these words are about the account or the recording, not the song
+const BOILERPLATE_KEYWORDS: [&str; 22] = [
+    "http",
+    "www.",
+    "点击",
+    "关注",
+    "扫码",
+    "二维码",
+    "长按",
+    "公众号",
+    "阅读原文",
+    "来源",
+    "版权",
+    "转载",
+    "投稿",
+    "微信",
+    "赞赏",
+    "分享",
+    "音频",
+    "视频",
+    "伴奏",
+    "下载",
+    "排版",
+    "编辑",
+];
+
+static KEY: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r"1\s*=\s*([#b♯♭]?[A-G][#b♯♭]?)").expect("valid key pattern")
+});
+static METER: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r"(?:^|[^\d/])(\d{1,2})\s*/\s*(2|4|8|16)(?:$|[^\d/])")
+        .expect("valid meter pattern")
+});
+static TEMPO: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r"(?i)(?:[♩♪]\s*=\s*|bpm\s*[=:：]?\s*)(\d{2,3})|(\d{2,3})\s*bpm")
+        .expect("valid tempo pattern")
+});
+// A credit starts the line or follows a separator, so that the 曲 of 歌曲 is
+// not taken for one
+static CREDIT: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(
+        r"(?:^|[\s/|｜，,;；、])(作词|作曲|词曲|编曲|编配|改编|词|曲|编)\s*[:：]\s*([^\s/|｜，,;；、:：]+)",
+    )
+    .expect("valid credit pattern")
+});
+
+/// Musical details and credits written in the article, such as `1=C 4/4 ♩=72`.
+#[derive(Default)]
+pub struct Annotations {
+    pub key: Option<String>,
+    pub meter: Option<String>,
+    /// Beats per minute
+    pub tempo: Option<u32>,
+    pub lyricist: Option<String>,
+    pub composer: Option<String>,
+    pub arranger: Option<String>,
+}
+
+impl Annotations {
+    pub fn is_empty(&self) -> bool {
+        self.key.is_none()
+            && self.meter.is_none()
+            && self.tempo.is_none()
+            && self.lyricist.is_none()
+            && self.composer.is_none()
+            && self.arranger.is_none()
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            key: self.key.clone(),
+            meter: self.meter.clone(),
+            tempo: self.tempo,
+            lyricist: self.lyricist.clone(),
+            composer: self.composer.clone(),
+            arranger: self.arranger.clone(),
+        }
+    }
+
+    pub fn from_json(value: &json::JsonValue) -> Annotations {
+        let get_string = |key: &str| value[key].as_str().map(std::string::ToString::to_string);
+        Annotations {
+            key: get_string("key"),
+            meter: get_string("meter"),
+            tempo: value["tempo"].as_u32(),
+            lyricist: get_string("lyricist"),
+            composer: get_string("composer"),
+            arranger: get_string("arranger"),
+        }
+    }
+
+    /// Read the fields found in a line and return whether there was any.
+    fn parse_line(&mut self, line: &str) -> bool {
+        let mut found = false;
+        if let Some(caps) = KEY.captures(line) {
+            self.key.get_or_insert_with(|| caps[1].to_string());
+            found = true;
+        }
+        if let Some(caps) = METER.captures(line) {
+            self.meter
+                .get_or_insert_with(|| format!("{}/{}", &caps[1], &caps[2]));
+            found = true;
+        }
+        if let Some(caps) = TEMPO.captures(line) {
+            let value = caps.get(1).or(caps.get(2)).map(|value| value.as_str());
+            if let Some(tempo) = value.and_then(|value| value.parse().ok()) {
+                self.tempo.get_or_insert(tempo);
+                found = true;
+            }
+        }
+        for caps in CREDIT.captures_iter(line) {
+            let name = caps[2].to_string();
+            let (lyricist, composer, arranger) = match &caps[1] {
+                "作词" | "词" => (true, false, false),
+                "作曲" | "曲" => (false, true, false),
+                "词曲" => (true, true, false),
+                _ => (false, false, true),
+            };
+            if lyricist {
+                self.lyricist.get_or_insert_with(|| name.clone());
+            }
+            if composer {
+                self.composer.get_or_insert_with(|| name.clone());
+            }
+            if arranger {
+                self.arranger.get_or_insert(name);
+            }
+            found = true;
+        }
+        found
+    }
+}
+
+/// The lyrics and the annotations found in the text of an article.
+#[derive(Default)]
+pub struct Lyrics {
+    /// Lyric lines, with an empty line between stanzas
+    pub lines: Vec<String>,
+    pub annotations: Annotations,
+}
+
+fn is_lyric_line(line: &str) -> bool {
+    line.chars().count() <= MAX_LYRIC_CHARS
+        && !line.contains('。')
+        && line.chars().any(char::is_alphabetic)
+        && !BOILERPLATE_KEYWORDS
+            .iter()
+            .any(|keyword| line.to_lowercase().contains(keyword))
+}
+
+/// Pick the lyrics out of the article text: the longest run of short lines,
+/// leaving out the annotations, the notes about the account and the titles
+/// that head a run.
+pub fn extract(body: &str, titles: &[&str]) -> Lyrics {
+    let mut annotations = Annotations::default();
+    let mut best: Vec<&str> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in body.lines().map(str::trim) {
+        if line.is_empty() {
+            if current.last().is_some_and(|last| !last.is_empty()) {
+                current.push("");
+            }
+            continue;
+        }
+        if annotations.parse_line(line) || (current.is_empty() && titles.contains(&line)) {
+            continue;
+        }
+        if is_lyric_line(line) {
+            current.push(line);
+        } else {
+            if count_lines(&current) > count_lines(&best) {
+                best = std::mem::take(&mut current);
+            }
+            current.clear();
+        }
+    }
+    if count_lines(&current) > count_lines(&best) {
+        best = current;
+    }
+    while best.last().is_some_and(|last| last.is_empty()) {
+        best.pop();
+    }
+
+    let lines = if count_lines(&best) >= MIN_LYRIC_LINES {
+        best.into_iter().map(str::to_string).collect()
+    } else {
+        Vec::new()
+    };
+    Lyrics { lines, annotations }
+}
+
+fn count_lines(lines: &[&str]) -> usize {
+    lines.iter().filter(|line| !line.is_empty()).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_credits() {
+        let mut annotations = Annotations::default();
+        assert!(annotations.parse_line("词：张三 曲：李四/编曲：王五"));
+        assert_eq!(annotations.lyricist.as_deref(), Some("张三"));
+        assert_eq!(annotations.composer.as_deref(), Some("李四"));
+        assert_eq!(annotations.arranger.as_deref(), Some("王五"));
+    }
+
+    #[test]
+    fn parse_credit_inside_a_word() {
+        let mut annotations = Annotations::default();
+        assert!(!annotations.parse_line("歌曲：主恩浩大"));
+        assert!(annotations.composer.is_none());
+    }
+}
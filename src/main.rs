@@ -1,13 +1,244 @@
+mod audio;
+mod booklet;
+mod catalog;
+mod classifier;
+mod config;
 mod errors;
+mod image_format;
+mod jobs;
+mod layout;
+mod lyrics;
+mod manifest;
+mod metadata;
+mod naming;
+mod numeric;
+mod omr;
+mod openlyrics;
+mod package;
+mod paths;
+mod pdf;
+mod phash;
+mod pinyin;
+mod postprocess;
+mod search;
+mod server;
 mod sheet;
+mod title;
 mod video;
 
 const URLS_FILE: &str = "urls.txt";
+const CONFIG_FILE: &str = "config.json";
 const OUTPUT_FOLDER: &str = "output";
 
+#[derive(clap::Parser)]
+#[command(version, about = "Parse the music sheet from weixin")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Download every article listed in urls.txt (the default)
+    Download,
+    /// Combine songs of the library into one PDF with a table of contents
+    Booklet {
+        /// Song folders, folder names under output/ or song titles
+        #[arg(required = true)]
+        songs: Vec<String>,
+        /// The PDF to create
+        #[arg(short, long, default_value = "booklet.pdf")]
+        output: String,
+        /// The title printed on the cover
+        #[arg(short, long, default_value = "Setlist")]
+        title: String,
+    },
+    /// Export the lyrics of songs as XML for worship presentation software
+    Openlyrics {
+        /// Song folders, folder names under output/ or song titles, every song if none
+        songs: Vec<String>,
+        /// The folder to write the XML files into
+        #[arg(short, long, default_value = "openlyrics")]
+        output: String,
+    },
+    /// Bundle songs with import CSVs for tablet score readers
+    Package {
+        /// Song folders, folder names under output/ or song titles, every song if none
+        songs: Vec<String>,
+        /// The folder to write the package into
+        #[arg(short, long, default_value = "package")]
+        output: String,
+    },
+    /// List the songs of the catalog
+    List,
+    /// Find songs by the words, pinyin or initials of their titles and lyrics
+    Search {
+        /// Chinese or English words, pinyin such as zhuenhaoda, or initials such as zehd
+        query: String,
+    },
+    /// Show the details and the files of a song
+    Show {
+        /// A folder name under output/ or a song title
+        song: String,
+    },
+    /// Rebuild the catalog from the song folders under output/
+    Reindex,
+    /// Browse the library in a web browser and accept jobs through its API
+    Serve {
+        /// The address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     pretty_env_logger::init();
+    let cli = <Cli as clap::Parser>::parse();
+    let config = config::Config::load(CONFIG_FILE)?;
+
+    match cli.command.unwrap_or(Command::Download) {
+        Command::Download => download(&config).await,
+        Command::Booklet {
+            songs,
+            output,
+            title,
+        } => {
+            let songs = find_songs(&songs)?;
+            booklet::create_booklet(
+                &songs,
+                &title,
+                &config.pdf,
+                &config.output.language,
+                &output,
+            )
+        }
+        Command::Openlyrics { songs, output } => {
+            std::fs::create_dir_all(&output)?;
+            let mut used = std::collections::HashSet::new();
+            let mut count = 0;
+            for song in find_songs(&songs)? {
+                if let Some(path) = openlyrics::export(&song, &output, &mut used)? {
+                    log::info!("Exported {path}");
+                    count += 1;
+                }
+            }
+            log::info!("Exported {count} songs to {output}");
+            Ok(())
+        }
+        Command::Package { songs, output } => {
+            let count = package::export(&find_songs(&songs)?, &config.pdf, &output)?;
+            log::info!("Packaged {count} songs into {output}");
+            Ok(())
+        }
+        Command::List => {
+            print_summaries(&catalog::Catalog::open(OUTPUT_FOLDER)?.list()?);
+            Ok(())
+        }
+        Command::Search { query } => {
+            print_summaries(&catalog::Catalog::open(OUTPUT_FOLDER)?.search(&query)?);
+            Ok(())
+        }
+        Command::Show { song } => {
+            print_record(&catalog::Catalog::open(OUTPUT_FOLDER)?.show(&song)?);
+            Ok(())
+        }
+        Command::Reindex => {
+            let count = catalog::Catalog::open(OUTPUT_FOLDER)?.reindex()?;
+            log::info!("Indexed {count} songs");
+            Ok(())
+        }
+        Command::Serve { address } => server::serve(OUTPUT_FOLDER, &address, config).await,
+    }
+}
+
+fn format_date(timestamp: Option<i64>) -> String {
+    timestamp
+        .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0))
+        .map(|date| {
+            date.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+fn print_summaries(summaries: &[catalog::Summary]) {
+    for summary in summaries {
+        println!(
+            "{}\t{}\t{}\t{OUTPUT_FOLDER}/{}",
+            format_date(summary.publish_time),
+            summary.title,
+            summary.account.as_deref().unwrap_or_default(),
+            summary.folder
+        );
+    }
+    println!("{} songs", summaries.len());
+}
+
+fn print_record(record: &catalog::Record) {
+    let annotations = &record.annotations;
+    let tempo = annotations.tempo.map(|tempo| tempo.to_string());
+    let date = Some(format_date(record.publish_time)).filter(|date| !date.is_empty());
+    let fields = [
+        ("Title", Some(&record.title)),
+        ("Song", record.song.as_ref()),
+        ("Artist", record.artist.as_ref()),
+        (
+            "Folder",
+            Some(&format!("{OUTPUT_FOLDER}/{}", record.folder)),
+        ),
+        ("URL", record.url.as_ref()),
+        ("Video URL", record.video_url.as_ref()),
+        ("Account", record.account.as_ref()),
+        ("Author", record.author.as_ref()),
+        ("Published", date.as_ref()),
+        ("Key", annotations.key.as_ref()),
+        ("Meter", annotations.meter.as_ref()),
+        ("Tempo", tempo.as_ref()),
+        ("Lyricist", annotations.lyricist.as_ref()),
+        ("Composer", annotations.composer.as_ref()),
+        ("Arranger", annotations.arranger.as_ref()),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            println!("{name}: {value}");
+        }
+    }
+    if !record.tags.is_empty() {
+        println!("Tags: {}", record.tags.join(", "));
+    }
+    println!("Files:");
+    for asset in &record.assets {
+        let line = [
+            Some(format!("{:<13}", asset.kind).as_str()),
+            Some(asset.file.as_str()),
+            asset.hash.as_deref(),
+            asset.source_url.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ");
+        println!("  {line}");
+    }
+}
+
+/// Find the songs given on the command line, or every song of the library.
+fn find_songs(queries: &[String]) -> anyhow::Result<Vec<booklet::Song>> {
+    if queries.is_empty() {
+        return Ok(paths::song_folders(OUTPUT_FOLDER)
+            .iter()
+            .filter_map(|folder| booklet::Song::load(folder).ok())
+            .collect());
+    }
+    queries
+        .iter()
+        .map(|query| booklet::Song::find(OUTPUT_FOLDER, query))
+        .collect()
+}
+
+async fn download(config: &config::Config) -> anyhow::Result<()> {
     let file_content = std::fs::read_to_string(URLS_FILE)?;
     let urls = file_content.split('\n');
     let mut failed_url = Vec::<&str>::new();
@@ -29,7 +260,8 @@ async fn main() -> anyhow::Result<()> {
         std::thread::sleep(std::time::Duration::new(5, 0));
 
         // Parse the resource
-        let sheet = match sheet::Sheet::try_new(url.to_string(), current_idx).await {
+        let video = video::Downloader20251220::get_url_by_index(current_idx).ok();
+        let sheet = match sheet::Sheet::try_new(url.to_string(), video, config).await {
             Ok(s) => s,
             Err(e) => {
                 log::error!("Failed to parse sheet: {e:?}");
@@ -0,0 +1,155 @@
+use std::io::Write;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+pub struct AudioEntry {
+    pub name: String,
+    pub file: String,
+    pub url: String,
+    pub accompaniment: bool,
+    pub duration: f64,
+}
+
+pub struct PageEntry {
+    pub file: String,
+    /// The URL found in the article
+    pub url: String,
+    /// The URL the page was actually downloaded from
+    pub download_url: String,
+    pub img_index: Option<u32>,
+    pub score: f64,
+    /// Perceptual hash of the downloaded image, of the first one if several were joined
+    pub hash: String,
+    /// The downloaded images `file` was made from, if it has been modified
+    pub originals: Vec<String>,
+    /// `MusicXML` files recognized from the page
+    pub musicxml: Vec<String>,
+    /// Why optical music recognition failed on the page
+    pub omr_error: Option<String>,
+}
+
+/// Describes what was downloaded into a song folder.
+pub struct Manifest {
+    pub url: String,
+    pub title: String,
+    pub audios: Vec<AudioEntry>,
+    pub pages: Vec<PageEntry>,
+    /// imgIndex values between the first and the last page that have no page
+    pub missing_img_indexes: Vec<u32>,
+    pub pdf: Option<String>,
+    /// The video paired with the article
+    pub video_url: Option<String>,
+    /// The downloaded video file
+    pub video: Option<String>,
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> json::JsonValue {
+        let audios = self
+            .audios
+            .iter()
+            .map(|audio| {
+                json::object! {
+                    name: audio.name.clone(),
+                    file: audio.file.clone(),
+                    url: audio.url.clone(),
+                    accompaniment: audio.accompaniment,
+                    duration: (audio.duration * 10.0).round() / 10.0,
+                }
+            })
+            .collect::<Vec<_>>();
+        let pages = self
+            .pages
+            .iter()
+            .map(|page| {
+                json::object! {
+                    file: page.file.clone(),
+                    url: page.url.clone(),
+                    download_url: page.download_url.clone(),
+                    img_index: page.img_index,
+                    score: (page.score * 100.0).round() / 100.0,
+                    hash: page.hash.clone(),
+                    originals: page.originals.clone(),
+                    musicxml: page.musicxml.clone(),
+                    omr_error: page.omr_error.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+        json::object! {
+            url: self.url.clone(),
+            title: self.title.clone(),
+            audios: audios,
+            pages: pages,
+            missing_img_indexes: self.missing_img_indexes.clone(),
+            pdf: self.pdf.clone(),
+            video_url: self.video_url.clone(),
+            video: self.video.clone(),
+        }
+    }
+
+    pub fn from_json(value: &json::JsonValue) -> Manifest {
+        let get_string = |value: &json::JsonValue| value.as_str().unwrap_or_default().to_string();
+        let get_strings = |value: &json::JsonValue| {
+            value
+                .members()
+                .filter_map(json::JsonValue::as_str)
+                .map(std::string::ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        let audios = value["audios"]
+            .members()
+            .map(|audio| AudioEntry {
+                name: get_string(&audio["name"]),
+                file: get_string(&audio["file"]),
+                url: get_string(&audio["url"]),
+                accompaniment: audio["accompaniment"].as_bool().unwrap_or_default(),
+                duration: audio["duration"].as_f64().unwrap_or_default(),
+            })
+            .collect();
+        let pages = value["pages"]
+            .members()
+            .map(|page| PageEntry {
+                file: get_string(&page["file"]),
+                url: get_string(&page["url"]),
+                download_url: get_string(&page["download_url"]),
+                img_index: page["img_index"].as_u32(),
+                score: page["score"].as_f64().unwrap_or_default(),
+                hash: get_string(&page["hash"]),
+                originals: get_strings(&page["originals"]),
+                musicxml: get_strings(&page["musicxml"]),
+                omr_error: page["omr_error"]
+                    .as_str()
+                    .map(std::string::ToString::to_string),
+            })
+            .collect();
+        Manifest {
+            url: get_string(&value["url"]),
+            title: get_string(&value["title"]),
+            audios,
+            pages,
+            missing_img_indexes: value["missing_img_indexes"]
+                .members()
+                .filter_map(json::JsonValue::as_u32)
+                .collect(),
+            pdf: value["pdf"].as_str().map(std::string::ToString::to_string),
+            video_url: value["video_url"]
+                .as_str()
+                .map(std::string::ToString::to_string),
+            video: value["video"]
+                .as_str()
+                .map(std::string::ToString::to_string),
+        }
+    }
+
+    /// Read the manifest of a song folder.
+    pub fn load(path: &str) -> anyhow::Result<Manifest> {
+        let content = std::fs::read_to_string(format!("{path}/{MANIFEST_FILE}"))?;
+        Ok(Manifest::from_json(&json::parse(&content)?))
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let mut file = std::fs::File::create(format!("{path}/{MANIFEST_FILE}"))?;
+        file.write_all(self.to_json().pretty(2).as_bytes())?;
+        Ok(())
+    }
+}
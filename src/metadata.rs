@@ -0,0 +1,84 @@
+use std::{collections::BTreeMap, io::Write};
+
+use crate::lyrics::Annotations;
+
+pub const METADATA_FILE: &str = "metadata.json";
+
+/// What is known about the article a song folder comes from.
+pub struct Metadata {
+    pub url: String,
+    pub title: String,
+    /// Named parts of the title, such as `song` and `artist`
+    pub title_parts: BTreeMap<String, String>,
+    pub account: Option<String>,
+    pub author: Option<String>,
+    /// Publish time of the article in seconds
+    pub publish_time: Option<i64>,
+    pub description: Option<String>,
+    pub cover: Option<String>,
+    /// Text of the article, one paragraph per line
+    pub body: Option<String>,
+    /// Key, tempo and credits written in the article
+    pub annotations: Annotations,
+    /// File of the lyrics taken from the article
+    pub lyrics: Option<String>,
+}
+
+impl Metadata {
+    pub fn to_json(&self) -> json::JsonValue {
+        let mut title_parts = json::JsonValue::new_object();
+        for (name, value) in &self.title_parts {
+            title_parts[name.as_str()] = value.clone().into();
+        }
+        let publish_date = self
+            .publish_time
+            .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0))
+            .map(|date| date.with_timezone(&chrono::Local).to_rfc3339());
+        json::object! {
+            url: self.url.clone(),
+            title: self.title.clone(),
+            title_parts: title_parts,
+            account: self.account.clone(),
+            author: self.author.clone(),
+            publish_time: self.publish_time,
+            publish_date: publish_date,
+            description: self.description.clone(),
+            cover: self.cover.clone(),
+            body: self.body.clone(),
+            annotations: self.annotations.to_json(),
+            lyrics: self.lyrics.clone(),
+        }
+    }
+
+    pub fn from_json(value: &json::JsonValue) -> Metadata {
+        let get_string = |key: &str| value[key].as_str().map(std::string::ToString::to_string);
+        Metadata {
+            url: get_string("url").unwrap_or_default(),
+            title: get_string("title").unwrap_or_default(),
+            title_parts: value["title_parts"]
+                .entries()
+                .filter_map(|(name, value)| Some((name.to_string(), value.as_str()?.to_string())))
+                .collect(),
+            account: get_string("account"),
+            author: get_string("author"),
+            publish_time: value["publish_time"].as_i64(),
+            description: get_string("description"),
+            cover: get_string("cover"),
+            body: get_string("body"),
+            annotations: Annotations::from_json(&value["annotations"]),
+            lyrics: get_string("lyrics"),
+        }
+    }
+
+    /// Read the metadata of a song folder.
+    pub fn load(path: &str) -> anyhow::Result<Metadata> {
+        let content = std::fs::read_to_string(format!("{path}/{METADATA_FILE}"))?;
+        Ok(Metadata::from_json(&json::parse(&content)?))
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let mut file = std::fs::File::create(format!("{path}/{METADATA_FILE}"))?;
+        file.write_all(self.to_json().pretty(2).as_bytes())?;
+        Ok(())
+    }
+}
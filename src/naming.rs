@@ -0,0 +1,187 @@
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use chrono::format::{Item, StrftimeItems};
+
+use crate::{config::OutputOptions, paths};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const FALLBACK_NAME: &str = "untitled";
+
+static PLACEHOLDER: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r"\{(\w+)(?::([^}]*))?\}").expect("valid placeholder pattern")
+});
+
+/// Fixed names of the assets in the configured language.
+fn fixed_names(language: &str) -> [(&'static str, &'static str); 2] {
+    match language {
+        "en" => [("accompaniment", "Accompaniment"), ("sheet", "Sheet music")],
+        "zh-Hant" => [("accompaniment", "伴奏"), ("sheet", "樂譜")],
+        _ => [("accompaniment", "伴奏"), ("sheet", "乐谱")],
+    }
+}
+
+/// Fills the naming templates of the output options with the values of a song.
+pub struct Naming<'a> {
+    options: &'a OutputOptions,
+    values: BTreeMap<String, String>,
+    date: Option<chrono::DateTime<chrono::Local>>,
+}
+
+impl Naming<'_> {
+    /// `timestamp` is the publish time of the article in seconds, `{date}` is
+    /// left empty if it is unknown.
+    pub fn new(
+        options: &OutputOptions,
+        mut values: BTreeMap<String, String>,
+        timestamp: Option<i64>,
+    ) -> Naming<'_> {
+        for (key, name) in fixed_names(&options.language) {
+            values.entry(key.to_string()).or_insert(name.to_string());
+        }
+        let date = timestamp
+            .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0))
+            .map(|date| date.with_timezone(&chrono::Local));
+        Naming {
+            options,
+            values,
+            date,
+        }
+    }
+
+    /// Replace `{name}` and `{name:spec}` placeholders. The spec of `{date}` is
+    /// a strftime format, and a number such as `{page:03}` pads the value with zeros.
+    pub fn render(&self, template: &str, extra: &[(&str, String)]) -> String {
+        PLACEHOLDER
+            .replace_all(template, |caps: &regex::Captures| {
+                let spec = caps.get(2).map(|spec| spec.as_str());
+                if &caps[1] == "date" {
+                    let format = spec.unwrap_or(DEFAULT_DATE_FORMAT);
+                    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                        log::warn!("Invalid date format {format}");
+                        return String::new();
+                    }
+                    return self
+                        .date
+                        .map(|date| date.format(format).to_string())
+                        .unwrap_or_default();
+                }
+                let value = extra
+                    .iter()
+                    .find(|(key, _)| *key == &caps[1])
+                    .map(|(_, value)| value)
+                    .or_else(|| self.values.get(&caps[1]))
+                    .cloned()
+                    .unwrap_or_default();
+                match spec.and_then(|spec| spec.parse::<usize>().ok()) {
+                    Some(width) => format!("{value:0>width$}"),
+                    None => value,
+                }
+            })
+            .to_string()
+    }
+
+    /// Path of the song folder relative to the output folder.
+    pub fn folder(&self) -> String {
+        let segments = self
+            .options
+            .folder
+            .split('/')
+            .map(|segment| paths::sanitize(&self.render(segment, &[])))
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let folder = if self.options.flat {
+            paths::sanitize(&segments.join(" - "))
+        } else {
+            segments.join("/")
+        };
+        if folder.is_empty() {
+            FALLBACK_NAME.to_string()
+        } else {
+            folder
+        }
+    }
+
+    /// Render a file name template, falling back to `fallback` if nothing is left.
+    pub fn file(
+        &self,
+        template: &str,
+        extra: &[(&str, String)],
+        fallback: &str,
+        extension: &str,
+    ) -> String {
+        let stem = paths::sanitize(&self.render(template, extra));
+        let stem = if stem.is_empty() { fallback } else { &stem };
+        if extension.is_empty() {
+            stem.to_string()
+        } else {
+            format!("{stem}.{extension}")
+        }
+    }
+
+    pub fn page(&self, number: usize, extension: &str) -> String {
+        self.file(
+            &self.options.page,
+            &[("page", number.to_string())],
+            &number.to_string(),
+            extension,
+        )
+    }
+
+    pub fn accompaniment(&self) -> String {
+        let fallback = fixed_names(&self.options.language)[0].1;
+        self.file(&self.options.accompaniment, &[], fallback, "mp3")
+    }
+
+    /// Name of an audio track without the extension.
+    pub fn audio(&self, name: &str, number: usize) -> String {
+        let extra = [("name", name.to_string()), ("number", number.to_string())];
+        self.file(&self.options.audio, &extra, &format!("audio{number}"), "")
+    }
+
+    /// Name of the video without the extension, which the downloader chooses.
+    pub fn video(&self) -> String {
+        self.file(&self.options.video, &[], FALLBACK_NAME, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(folder: &str) -> OutputOptions {
+        OutputOptions {
+            folder: folder.to_string(),
+            ..OutputOptions::default()
+        }
+    }
+
+    #[test]
+    fn date_of_the_article() {
+        let options = options("{date:%Y}/{title}");
+        let values = BTreeMap::from([("title".to_string(), "Amazing Grace".to_string())]);
+        // Noon UTC is the same day in every time zone
+        let naming = Naming::new(&options, values, Some(1_700_049_600));
+        assert_eq!(naming.folder(), "2023/Amazing Grace");
+        assert_eq!(naming.render("{date}", &[]), "2023-11-15");
+    }
+
+    #[test]
+    fn unknown_date_is_left_out() {
+        let options = options("{date:%Y}/{title}");
+        let values = BTreeMap::from([("title".to_string(), "Amazing Grace".to_string())]);
+        let naming = Naming::new(&options, values, None);
+        assert_eq!(naming.folder(), "Amazing Grace");
+        assert_eq!(naming.render("{title} {date}", &[]), "Amazing Grace ");
+    }
+
+    #[test]
+    fn pad_numbers() {
+        let options = options("{title}");
+        let naming = Naming::new(&options, BTreeMap::new(), None);
+        assert_eq!(
+            naming.render("{page:03}", &[("page", "7".to_string())]),
+            "007"
+        );
+        assert_eq!(naming.folder(), FALLBACK_NAME);
+    }
+}
@@ -0,0 +1,30 @@
+// Conversions between numeric types, for counts, pixels and page sizes that
+// are far from the limits of either type
+
+/// Round to the nearest pixel, negative values becoming zero.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn to_u32(value: f64) -> u32 {
+    value.round().max(0.0) as u32
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub fn to_f64(value: u64) -> f64 {
+    value as f64
+}
+
+pub fn to_usize(value: u32) -> usize {
+    value as usize
+}
+
+pub fn to_u64(value: usize) -> u64 {
+    value as u64
+}
+
+pub fn count_to_f64(count: usize) -> f64 {
+    to_f64(to_u64(count))
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub fn to_f32(value: u32) -> f32 {
+    value as f32
+}
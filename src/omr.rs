@@ -0,0 +1,84 @@
+use std::process::Command;
+
+use crate::config::OmrOptions;
+
+const MUSICXML_EXTENSIONS: [&str; 3] = ["mxl", "musicxml", "xml"];
+
+/// Collect the `MusicXML` files written anywhere under the folder.
+fn find_musicxml(folder: &std::path::Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(find_musicxml(&path)?);
+        } else if path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                MUSICXML_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+            })
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Run the OMR engine on one page of a song folder and move the resulting
+/// `MusicXML` files into the OMR folder. Returns their paths relative to the
+/// song folder.
+pub fn recognize(path: &str, page: &str, options: &OmrOptions) -> anyhow::Result<Vec<String>> {
+    let stem = page.rsplit_once('.').map_or(page, |(stem, _)| stem);
+    // Let the engine write into a folder of its own, whatever layout it uses
+    let work = format!("{path}/{}/.{stem}", options.folder);
+    std::fs::create_dir_all(&work)?;
+    let args = options
+        .args
+        .iter()
+        .map(|arg| {
+            arg.replace("{input}", &format!("{path}/{page}"))
+                .replace("{output}", &work)
+        })
+        .collect::<Vec<_>>();
+    let output = Command::new(&options.command)
+        .args(&args)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Cannot run {}: {e}", options.command));
+    let result = output.and_then(|output| {
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let last_line = stderr.lines().rev().find(|line| !line.trim().is_empty());
+            return Err(anyhow::anyhow!(
+                "{} failed with {}: {}",
+                options.command,
+                output.status,
+                last_line.unwrap_or_default().trim()
+            ));
+        }
+        let files = find_musicxml(std::path::Path::new(&work))?;
+        if files.is_empty() {
+            return Err(anyhow::anyhow!("{} recognized no music", options.command));
+        }
+        files
+            .iter()
+            .enumerate()
+            .map(|(idx, file)| {
+                let extension = file
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                // Scores with several movements give several files
+                let name = if idx == 0 {
+                    format!("{}/{stem}.{extension}", options.folder)
+                } else {
+                    format!("{}/{stem}-{}.{extension}", options.folder, idx + 1)
+                };
+                std::fs::rename(file, format!("{path}/{name}"))?;
+                Ok(name)
+            })
+            .collect()
+    });
+    std::fs::remove_dir_all(&work)?;
+    result
+}
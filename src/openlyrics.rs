@@ -0,0 +1,253 @@
+use std::{collections::HashSet, io::Write, sync::LazyLock};
+
+use crate::{booklet::Song, lyrics, metadata::Metadata, paths};
+
+const NAMESPACE: &str = "http://openlyrics.info/namespace/2009/song";
+
+struct Verse {
+    name: String,
+    lines: Vec<String>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+static LABEL: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(
+        r"(?i)^(?<open>[\[【(（])?\s*(?<word>主歌|副歌|桥段|导歌|尾声|verse|chorus|bridge|pre-?chorus|ending|[vcbpe])\s*(?<number>\d*)\s*(?<close>[\]】)）])?\s*(?<colon>[:：])?\s*(?<rest>.*)$",
+    )
+    .expect("valid label pattern")
+});
+
+/// Recognize a label such as `副歌`, `[V2]` or `Chorus:` at the start of a
+/// stanza and return the kind of verse, its number and the rest of the line.
+fn parse_label(line: &str) -> Option<(char, Option<u32>, String)> {
+    let caps = LABEL.captures(line)?;
+    let word = caps["word"].to_lowercase();
+    let marked = caps.name("open").is_some() || caps.name("colon").is_some();
+    let rest = caps["rest"].trim().to_string();
+    // A bare word or letter is only a label if it stands alone or is marked as one
+    if !(marked || rest.is_empty())
+        || (word.chars().count() == 1 && !marked && caps["number"].is_empty())
+    {
+        return None;
+    }
+    let kind = match word.as_str() {
+        "主歌" | "verse" | "v" => 'v',
+        "副歌" | "chorus" | "c" => 'c',
+        "桥段" | "bridge" | "b" => 'b',
+        "导歌" | "prechorus" | "pre-chorus" | "p" => 'p',
+        _ => 'e',
+    };
+    Some((kind, caps["number"].parse().ok(), rest))
+}
+
+/// Split the lyrics into verses at the empty lines and name them from their
+/// labels, or from repetition: a stanza that comes back is a chorus.
+fn infer_verses(lines: &[String]) -> (Vec<Verse>, Vec<String>) {
+    let stanzas = lines
+        .split(String::is_empty)
+        .filter(|stanza| !stanza.is_empty())
+        .collect::<Vec<_>>();
+    let mut verses = Vec::<Verse>::new();
+    let mut order = Vec::new();
+    for stanza in &stanzas {
+        let label = parse_label(&stanza[0]);
+        let lines = match &label {
+            Some((_, _, rest)) => std::iter::once(rest.clone())
+                .filter(|rest| !rest.is_empty())
+                .chain(stanza[1..].iter().cloned())
+                .collect::<Vec<_>>(),
+            None => stanza.to_vec(),
+        };
+        let existing = verses.iter().find(|verse| {
+            if lines.is_empty() {
+                // A label alone repeats the verse of that kind
+                label
+                    .as_ref()
+                    .is_some_and(|(kind, _, _)| verse.name.starts_with(*kind))
+            } else {
+                verse.lines == lines
+            }
+        });
+        if let Some(verse) = existing {
+            order.push(verse.name.clone());
+            continue;
+        }
+        if lines.is_empty() {
+            continue;
+        }
+        let (kind, number) = match label {
+            Some((kind, number, _)) => (kind, number),
+            None if stanzas.iter().filter(|other| other == &stanza).count() > 1 => ('c', None),
+            None => ('v', None),
+        };
+        let mut number = number.unwrap_or_else(|| {
+            1 + u32::try_from(
+                verses
+                    .iter()
+                    .filter(|verse| verse.name.starts_with(kind))
+                    .count(),
+            )
+            .unwrap_or(0)
+        });
+        while verses
+            .iter()
+            .any(|verse| verse.name == format!("{kind}{number}"))
+        {
+            number += 1;
+        }
+        let name = format!("{kind}{number}");
+        order.push(name.clone());
+        verses.push(Verse { name, lines });
+    }
+    (verses, order)
+}
+
+fn get_authors(metadata: &Metadata) -> Vec<String> {
+    let annotations = &metadata.annotations;
+    let credits = [
+        ("words", &annotations.lyricist),
+        ("music", &annotations.composer),
+        ("arrangement", &annotations.arranger),
+    ];
+    let authors = credits
+        .iter()
+        .filter_map(|(kind, name)| {
+            let name = name.as_ref()?;
+            Some(format!(
+                "      <author type=\"{kind}\">{}</author>",
+                escape(name)
+            ))
+        })
+        .collect::<Vec<_>>();
+    if !authors.is_empty() {
+        return authors;
+    }
+    // Without credits, the artist of the title is the best guess
+    metadata
+        .title_parts
+        .get("artist")
+        .map(|artist| format!("      <author>{}</author>", escape(artist)))
+        .into_iter()
+        .collect()
+}
+
+/// Write an `OpenLyrics` 0.9 document.
+fn to_xml(title: &str, metadata: Option<&Metadata>, lines: &[String]) -> String {
+    let (verses, order) = infer_verses(lines);
+    let mut xml = vec![
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>".to_string(),
+        format!(
+            "<song xmlns=\"{NAMESPACE}\" version=\"0.9\" createdIn=\"{} {}\" modifiedDate=\"{}\">",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+        ),
+        "  <properties>".to_string(),
+        "    <titles>".to_string(),
+        format!("      <title>{}</title>", escape(title)),
+        "    </titles>".to_string(),
+    ];
+
+    if let Some(metadata) = metadata {
+        let authors = get_authors(metadata);
+        if !authors.is_empty() {
+            xml.push("    <authors>".to_string());
+            xml.extend(authors);
+            xml.push("    </authors>".to_string());
+        }
+        let annotations = &metadata.annotations;
+        if let Some(tempo) = annotations.tempo {
+            xml.push(format!("    <tempo type=\"bpm\">{tempo}</tempo>"));
+        }
+        if let Some(key) = &annotations.key {
+            xml.push(format!("    <key>{}</key>", escape(key)));
+        }
+    }
+    xml.push(format!("    <verseOrder>{}</verseOrder>", order.join(" ")));
+    let comments = metadata
+        .into_iter()
+        .flat_map(|metadata| {
+            [
+                metadata
+                    .annotations
+                    .meter
+                    .as_ref()
+                    .map(|meter| format!("Meter: {meter}")),
+                Some(&metadata.url)
+                    .filter(|url| !url.is_empty())
+                    .map(|url| format!("Source: {url}")),
+            ]
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+    if !comments.is_empty() {
+        xml.push("    <comments>".to_string());
+        for comment in comments {
+            xml.push(format!("      <comment>{}</comment>", escape(&comment)));
+        }
+        xml.push("    </comments>".to_string());
+    }
+    xml.push("  </properties>".to_string());
+
+    xml.push("  <lyrics>".to_string());
+    for verse in verses {
+        let lines = verse
+            .lines
+            .iter()
+            .map(|line| escape(line))
+            .collect::<Vec<_>>()
+            .join("<br/>");
+        xml.push(format!("    <verse name=\"{}\">", verse.name));
+        xml.push(format!("      <lines>{lines}</lines>"));
+        xml.push("    </verse>".to_string());
+    }
+    xml.push("  </lyrics>".to_string());
+    xml.push("</song>\n".to_string());
+    xml.join("\n")
+}
+
+/// Export the lyrics of a song into the folder and return the path of the file,
+/// or `None` if the song has no lyrics. `used` holds the file names taken so far.
+pub fn export(
+    song: &Song,
+    folder: &str,
+    used: &mut HashSet<String>,
+) -> anyhow::Result<Option<String>> {
+    let metadata = Metadata::load(&song.folder).ok();
+    let lyrics_file = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.lyrics.clone())
+        .unwrap_or_else(|| lyrics::LYRICS_FILE.to_string());
+    let Ok(content) = std::fs::read_to_string(format!("{}/{lyrics_file}", song.folder)) else {
+        log::warn!("Skipping {}, it has no lyrics", song.folder);
+        return Ok(None);
+    };
+    let lines = content
+        .lines()
+        .map(|line| line.trim().to_string())
+        .collect::<Vec<_>>();
+    let title = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.title_parts.get("song"))
+        .unwrap_or(&song.title);
+
+    let stem = Some(paths::sanitize(&song.title))
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or_else(|| "song".to_string());
+    let mut file_name = format!("{stem}.xml");
+    let mut suffix = 2;
+    while !used.insert(file_name.clone()) {
+        file_name = format!("{stem} ({suffix}).xml");
+        suffix += 1;
+    }
+    let path = format!("{folder}/{file_name}");
+    let mut file = std::fs::File::create(&path)?;
+    file.write_all(to_xml(title, metadata.as_ref(), &lines).as_bytes())?;
+    Ok(Some(path))
+}
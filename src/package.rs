@@ -0,0 +1,215 @@
+use std::{collections::HashSet, io::Write};
+
+use crate::{
+    booklet::Song, config::PdfOptions, manifest::Manifest, metadata::Metadata, paths, pdf,
+};
+
+pub const CSV_FILE: &str = "library.csv";
+pub const FORSCORE_CSV_FILE: &str = "forscore.csv";
+// Field names of the MobileSheets CSV import
+const CSV_HEADER: [&str; 8] = [
+    "Title",
+    "Artists",
+    "Composers",
+    "Keys",
+    "Tempos",
+    "Signatures",
+    "Files",
+    "Audio Files",
+];
+
+// Columns of the forScore CSV import, which start with the file name. The
+// key is the number of sharps (or of flats, below zero) and whether it is minor
+const FORSCORE_CSV_HEADER: [&str; 13] = [
+    "filename",
+    "title",
+    "composer",
+    "genre",
+    "tags",
+    "labels",
+    "reference",
+    "rating",
+    "difficulty",
+    "minutes",
+    "seconds",
+    "keysf",
+    "keymi",
+];
+
+/// The number of sharps, or of flats below zero, of a major key such as
+/// `bE` or `F#`.
+fn key_signature(key: &str) -> Option<i32> {
+    let mut fifths = None;
+    let mut accidental = 0;
+    for c in key.chars() {
+        match c {
+            '#' | '♯' => accidental += 7,
+            'b' | '♭' => accidental -= 7,
+            'C' => fifths = Some(0),
+            'G' => fifths = Some(1),
+            'D' => fifths = Some(2),
+            'A' => fifths = Some(3),
+            'E' => fifths = Some(4),
+            'B' => fifths = Some(5),
+            'F' => fifths = Some(-1),
+            _ => return None,
+        }
+    }
+    Some(fifths? + accidental).filter(|fifths| (-7..=7).contains(fifths))
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Find the accompaniment of a song, or else its first audio track.
+fn find_audio(song: &Song, manifest: Option<&Manifest>) -> Option<String> {
+    if let Some(manifest) = manifest {
+        return manifest
+            .audios
+            .iter()
+            .find(|audio| audio.accompaniment)
+            .or_else(|| manifest.audios.first())
+            .map(|audio| format!("{}/{}", song.folder, audio.file));
+    }
+    // Older folders have no manifest
+    let mut audios = std::fs::read_dir(&song.folder)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("mp3"))
+        })
+        .map(|path| path.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    audios.sort();
+    audios.into_iter().next()
+}
+
+/// Copy the PDF of the song into the package, or create one from its pages.
+fn write_pdf(
+    song: &Song,
+    manifest: Option<&Manifest>,
+    options: &PdfOptions,
+    path: &str,
+) -> anyhow::Result<()> {
+    if let Some(pdf) = manifest.and_then(|manifest| manifest.pdf.as_ref()) {
+        let source = format!("{}/{pdf}", song.folder);
+        if std::path::Path::new(&source).is_file() {
+            std::fs::copy(source, path)?;
+            return Ok(());
+        }
+    }
+    let mut builder = pdf::PdfBuilder::new(options);
+    for page in &song.pages {
+        builder.add_image_page(&std::fs::read(page)?, None)?;
+    }
+    let info = pdf::DocumentInfo {
+        title: song.title.clone(),
+        author: None,
+        source: manifest
+            .map(|manifest| manifest.url.clone())
+            .unwrap_or_default(),
+    };
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(&builder.finish(&info))?;
+    Ok(())
+}
+
+fn write_csv(path: &str, rows: &[Vec<Option<String>>]) -> anyhow::Result<()> {
+    let mut content = String::new();
+    for row in rows {
+        let fields = row
+            .iter()
+            .map(|field| escape_csv(field.as_deref().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        content += &fields.join(",");
+        content += "\r\n";
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// Bundle the PDF and the accompaniment of every song into the folder, with
+/// CSVs that tablet score readers import in one step. Returns the number
+/// of songs in the package.
+pub fn export(songs: &[Song], options: &PdfOptions, folder: &str) -> anyhow::Result<usize> {
+    std::fs::create_dir_all(folder)?;
+    let header = |names: &[&str]| names.iter().map(|name| Some((*name).to_string())).collect();
+    let mut rows: Vec<Vec<Option<String>>> = vec![header(&CSV_HEADER)];
+    let mut forscore_rows: Vec<Vec<Option<String>>> = vec![header(&FORSCORE_CSV_HEADER)];
+    let mut used = HashSet::new();
+    for song in songs {
+        if song.pages.is_empty() {
+            log::warn!("Skipping {}, it has no sheet pages", song.folder);
+            continue;
+        }
+        log::info!("Packaging {}...", song.title);
+        let manifest = Manifest::load(&song.folder).ok();
+        let metadata = Metadata::load(&song.folder).ok();
+
+        let stem = Some(paths::sanitize(&song.title))
+            .filter(|stem| !stem.is_empty())
+            .unwrap_or_else(|| "song".to_string());
+        let mut unique_stem = stem.clone();
+        let mut suffix = 2;
+        while !used.insert(unique_stem.clone()) {
+            unique_stem = format!("{stem} ({suffix})");
+            suffix += 1;
+        }
+        let pdf_file = format!("{unique_stem}.pdf");
+        write_pdf(
+            song,
+            manifest.as_ref(),
+            options,
+            &format!("{folder}/{pdf_file}"),
+        )?;
+        let audio_file = match find_audio(song, manifest.as_ref()) {
+            Some(audio) => {
+                let audio_file = format!("{unique_stem}.mp3");
+                std::fs::copy(audio, format!("{folder}/{audio_file}"))?;
+                audio_file
+            }
+            None => String::new(),
+        };
+
+        let annotations = metadata.as_ref().map(|metadata| &metadata.annotations);
+        let title = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.title_parts.get("song"))
+            .unwrap_or(&song.title);
+        let artist = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.title_parts.get("artist"));
+        let composer = annotations.and_then(|annotations| annotations.composer.clone());
+        let key = annotations.and_then(|annotations| annotations.key.clone());
+        rows.push(vec![
+            Some(title.clone()),
+            artist.cloned(),
+            composer.clone(),
+            key.clone(),
+            annotations
+                .and_then(|annotations| annotations.tempo)
+                .map(|tempo| tempo.to_string()),
+            annotations.and_then(|annotations| annotations.meter.clone()),
+            Some(pdf_file.clone()),
+            Some(audio_file),
+        ]);
+        let key_signature = key.as_deref().and_then(key_signature);
+        let mut forscore_row = vec![Some(pdf_file), Some(title.clone()), composer];
+        forscore_row.resize(FORSCORE_CSV_HEADER.len() - 2, None);
+        forscore_row.push(key_signature.map(|fifths| fifths.to_string()));
+        // Numbered musical notation gives the major key
+        forscore_row.push(key_signature.map(|_| "0".to_string()));
+        forscore_rows.push(forscore_row);
+    }
+
+    write_csv(&format!("{folder}/{CSV_FILE}"), &rows)?;
+    write_csv(&format!("{folder}/{FORSCORE_CSV_FILE}"), &forscore_rows)?;
+    Ok(rows.len() - 1)
+}
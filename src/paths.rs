@@ -0,0 +1,151 @@
+use unicode_normalization::UnicodeNormalization;
+
+// Leaves room for a collision suffix and an extension under the usual 255 byte limit
+const MAX_NAME_BYTES: usize = 200;
+// Names that Windows refuses whatever the extension
+const RESERVED_NAMES: [&str; 24] = [
+    "CON", "PRN", "AUX", "NUL", "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7",
+    "COM8", "COM9", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+fn trim(name: &str) -> &str {
+    name.trim_matches(|c: char| c.is_whitespace() || c == '.')
+}
+
+/// Make a name usable as a file or folder name on Linux, Windows and exFAT,
+/// or return an empty string if nothing is left of it.
+pub fn sanitize(name: &str) -> String {
+    let name = name
+        .nfc()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let mut name = trim(&name).to_string();
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        name.insert(stem.len(), '_');
+    }
+
+    // Cut last so that nothing makes the name longer again
+    if name.len() > MAX_NAME_BYTES {
+        let mut end = MAX_NAME_BYTES;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+        name = trim(&name).to_string();
+    }
+    name
+}
+
+/// Whether the folder holds the article with this URL.
+fn is_same_article(folder: &str, url: &str) -> bool {
+    std::fs::read_to_string(format!("{folder}/README")).is_ok_and(|readme| readme.trim() == url)
+}
+
+/// Choose the folder of an article under `parent` from a safe relative path.
+/// A folder of the same name that holds another article gets a ` (2)`,
+/// ` (3)`... suffix, while the folder of the same article is reused.
+pub fn unique_folder(parent: &str, name: &str, url: &str) -> String {
+    let mut folder = format!("{parent}/{name}");
+    let mut idx = 1;
+    while std::path::Path::new(&folder).exists() && !is_same_article(&folder, url) {
+        idx += 1;
+        folder = format!("{parent}/{name} ({idx})");
+    }
+    folder
+}
+
+/// Find the song folders under the output folder, however deep the layout is.
+pub fn song_folders(output: &str) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(output) else {
+        return Vec::new();
+    };
+    let mut folders = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let folder = path.to_string_lossy().to_string();
+        if path.join("README").is_file() || path.join(crate::manifest::MANIFEST_FILE).is_file() {
+            folders.push(folder);
+        } else {
+            folders.extend(song_folders(&folder));
+        }
+    }
+    folders.sort();
+    folders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_forbidden_characters() {
+        assert_eq!(sanitize("a/b:c?d*e\"f"), "a_b_c_d_e_f");
+        assert_eq!(sanitize("a\\b<c>d|e\tf"), "a_b_c_d_e_f");
+    }
+
+    #[test]
+    fn sanitize_trailing_dots_and_spaces() {
+        assert_eq!(sanitize("  Amazing Grace. . "), "Amazing Grace");
+        assert_eq!(sanitize("..."), "");
+    }
+
+    #[test]
+    fn sanitize_long_cjk_name_on_char_boundary() {
+        let name = "主".repeat(100);
+        assert_eq!(name.len(), 300);
+        assert_eq!(sanitize(&name), "主".repeat(66));
+    }
+
+    #[test]
+    fn sanitize_reserved_names() {
+        assert_eq!(sanitize("CON"), "CON_");
+        assert_eq!(sanitize("con.txt"), "con_.txt");
+        assert_eq!(sanitize("LPT1"), "LPT1_");
+        assert_eq!(sanitize("COM0"), "COM0_");
+        assert_eq!(sanitize("CONCERT"), "CONCERT");
+        let long = sanitize(&format!("aux.{}", "a".repeat(300)));
+        assert!(long.starts_with("aux_."));
+        assert_eq!(long.len(), MAX_NAME_BYTES);
+    }
+
+    #[test]
+    fn sanitize_nfd_input() {
+        let decomposed = "Cafe\u{301} 主恩";
+        assert_eq!(sanitize(decomposed), "Caf\u{e9} 主恩");
+    }
+
+    #[test]
+    fn unique_folder_suffixes() {
+        let parent = std::env::temp_dir().join(format!("paths-test-{}", std::process::id()));
+        let parent = parent.to_string_lossy().to_string();
+        let folder = format!("{parent}/Song");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(format!("{folder}/README"), "https://example.com/a").unwrap();
+
+        assert_eq!(
+            unique_folder(&parent, "Song", "https://example.com/a"),
+            folder
+        );
+        assert_eq!(
+            unique_folder(&parent, "Song", "https://example.com/b"),
+            format!("{parent}/Song (2)")
+        );
+        assert_eq!(
+            unique_folder(&parent, "New", "https://example.com/b"),
+            format!("{parent}/New")
+        );
+        std::fs::remove_dir_all(&parent).unwrap();
+    }
+}
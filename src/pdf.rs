@@ -0,0 +1,406 @@
+use image::GenericImageView;
+use pdf_writer::{
+    types::{ActionType, AnnotationType, CidFontType, FontFlags},
+    Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr,
+};
+
+use crate::{config::PdfOptions, image_format::ImageFormat, numeric::to_f32};
+
+const POINTS_PER_MM: f32 = 72.0 / 25.4;
+// A CJK font that PDF readers provide themselves, so nothing has to be embedded
+const CJK_FONT: &[u8] = b"STSong-Light";
+const FONT_NAME: Name<'static> = Name(b"F1");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSize {
+    A4,
+    Letter,
+}
+
+impl PageSize {
+    pub fn from_name(name: &str) -> Option<PageSize> {
+        match name.to_ascii_lowercase().as_str() {
+            "a4" => Some(PageSize::A4),
+            "letter" => Some(PageSize::Letter),
+            _ => None,
+        }
+    }
+
+    /// Width and height in points.
+    pub fn dimensions(self) -> (f32, f32) {
+        match self {
+            PageSize::A4 => (595.28, 841.89),
+            PageSize::Letter => (612.0, 792.0),
+        }
+    }
+}
+
+/// Metadata stored in the document information dictionary.
+pub struct DocumentInfo {
+    pub title: String,
+    pub author: Option<String>,
+    pub source: String,
+}
+
+/// Approximate width of the text in points, with full-width CJK glyphs
+/// and half-width Latin glyphs.
+pub fn text_width(text: &str, size: f32) -> f32 {
+    text.chars()
+        .map(|c| if c.is_ascii() { 0.5 } else { 1.0 })
+        .sum::<f32>()
+        * size
+}
+
+/// Encode the text as UCS-2 for the encoding of the CJK font.
+fn encode_text(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        // Characters outside of the BMP have no glyph anyway
+        .filter(|unit| !(0xD800..0xE000).contains(unit))
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+/// A line of text placed on a page, optionally linking to another page.
+pub struct TextItem {
+    pub text: String,
+    pub size: f32,
+    pub x: f32,
+    pub y: f32,
+    pub link: Option<Ref>,
+}
+
+/// An image ready to be embedded into the PDF.
+struct EncodedImage {
+    width: u32,
+    height: u32,
+    gray: bool,
+    filter: Filter,
+    data: Vec<u8>,
+}
+
+impl EncodedImage {
+    fn new(binary: &[u8]) -> anyhow::Result<EncodedImage> {
+        let image = image::load_from_memory(binary)?;
+        let (width, height) = image.dimensions();
+
+        // JPEG can be embedded as is if PDF understands its color space
+        if ImageFormat::detect(binary) == Some(ImageFormat::Jpeg) {
+            let gray = match image.color() {
+                image::ColorType::L8 => Some(true),
+                image::ColorType::Rgb8 => Some(false),
+                _ => None,
+            };
+            if let Some(gray) = gray {
+                return Ok(EncodedImage {
+                    width,
+                    height,
+                    gray,
+                    filter: Filter::DctDecode,
+                    data: binary.to_vec(),
+                });
+            }
+        }
+
+        // Flatten transparency onto white paper
+        let rgb = image
+            .to_rgba8()
+            .pixels()
+            .flat_map(|p| {
+                let alpha = u16::from(p.0[3]);
+                p.0[..3].iter().map(move |&c| {
+                    let blended = (u16::from(c) * alpha + 255 * (255 - alpha)) / 255;
+                    u8::try_from(blended).unwrap_or(u8::MAX)
+                })
+            })
+            .collect::<Vec<_>>();
+        let gray = rgb.chunks_exact(3).all(|p| p[0] == p[1] && p[1] == p[2]);
+        let samples = if gray {
+            rgb.chunks_exact(3).map(|p| p[0]).collect()
+        } else {
+            rgb
+        };
+        Ok(EncodedImage {
+            width,
+            height,
+            gray,
+            filter: Filter::FlateDecode,
+            data: miniz_oxide::deflate::compress_to_vec_zlib(&samples, 6),
+        })
+    }
+}
+
+/// Builds a PDF document page by page.
+pub struct PdfBuilder {
+    pdf: Pdf,
+    options: PdfOptions,
+    next_id: i32,
+    catalog_id: Ref,
+    page_tree_id: Ref,
+    page_ids: Vec<Ref>,
+    font_id: Option<Ref>,
+    outlines: Vec<(String, Ref)>,
+}
+
+impl PdfBuilder {
+    pub fn new(options: &PdfOptions) -> PdfBuilder {
+        PdfBuilder {
+            pdf: Pdf::new(),
+            options: options.clone(),
+            next_id: 3,
+            catalog_id: Ref::new(1),
+            page_tree_id: Ref::new(2),
+            page_ids: Vec::new(),
+            font_id: None,
+            outlines: Vec::new(),
+        }
+    }
+
+    fn alloc(&mut self) -> Ref {
+        let id = Ref::new(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Width and height of the pages in points.
+    pub fn page_dimensions(&self) -> (f32, f32) {
+        self.options.page_size.dimensions()
+    }
+
+    /// Margin of the pages in points.
+    pub fn margin(&self) -> f32 {
+        self.options.margin_mm * POINTS_PER_MM
+    }
+
+    /// Reserve the next page, so that other pages can link to it before it is written.
+    pub fn alloc_page(&mut self) -> Ref {
+        let page_id = self.alloc();
+        self.page_ids.push(page_id);
+        page_id
+    }
+
+    /// Number of pages so far, including the reserved ones.
+    pub fn page_count(&self) -> usize {
+        self.page_ids.len()
+    }
+
+    fn font(&mut self) -> Ref {
+        if let Some(font_id) = self.font_id {
+            return font_id;
+        }
+        let font_id = self.alloc();
+        let cid_font_id = self.alloc();
+        let descriptor_id = self.alloc();
+        self.pdf
+            .type0_font(font_id)
+            .base_font(Name(CJK_FONT))
+            .encoding_predefined(Name(b"UniGB-UCS2-H"))
+            .descendant_font(cid_font_id);
+        self.pdf
+            .cid_font(cid_font_id)
+            .subtype(CidFontType::Type0)
+            .base_font(Name(CJK_FONT))
+            .system_info(pdf_writer::types::SystemInfo {
+                registry: Str(b"Adobe"),
+                ordering: Str(b"GB1"),
+                supplement: 2,
+            })
+            .font_descriptor(descriptor_id)
+            .default_width(1000.0)
+            .widths()
+            .same(1, 95, 500.0);
+        self.pdf
+            .font_descriptor(descriptor_id)
+            .name(Name(CJK_FONT))
+            .flags(FontFlags::SERIF | FontFlags::SYMBOLIC)
+            .bbox(Rect::new(-25.0, -254.0, 1000.0, 880.0))
+            .italic_angle(0.0)
+            .ascent(880.0)
+            .descent(-120.0)
+            .cap_height(880.0)
+            .stem_v(93.0);
+        self.font_id = Some(font_id);
+        font_id
+    }
+
+    fn write_text(content: &mut Content, item: &TextItem) {
+        content.begin_text();
+        content.set_font(FONT_NAME, item.size);
+        content.next_line(item.x, item.y);
+        content.show(Str(&encode_text(&item.text)));
+        content.end_text();
+    }
+
+    /// Add a page showing the image, scaled to fit inside the margins, with an
+    /// optional footer such as the page number.
+    pub fn add_image_page(&mut self, binary: &[u8], footer: Option<&str>) -> anyhow::Result<Ref> {
+        let image = EncodedImage::new(binary)?;
+        let page_id = self.alloc_page();
+        let content_id = self.alloc();
+        let image_id = self.alloc();
+        let image_name = Name(b"Im1");
+        let font_id = footer.map(|_| self.font());
+
+        let (page_width, page_height) = self.page_dimensions();
+        let margin = self.margin();
+        let (image_width, image_height) = (to_f32(image.width), to_f32(image.height));
+        let fit = ((page_width - 2.0 * margin) / image_width)
+            .min((page_height - 2.0 * margin) / image_height);
+        // Treat a pixel as a point, and only shrink unless asked to fill the page
+        let scale = if self.options.fit_to_page {
+            fit
+        } else {
+            fit.min(1.0)
+        };
+        let (width, height) = (image_width * scale, image_height * scale);
+        let x = (page_width - width) / 2.0;
+        let y = (page_height - height) / 2.0;
+
+        let mut xobject = self.pdf.image_xobject(image_id, &image.data);
+        xobject.filter(image.filter);
+        xobject.width(i32::try_from(image.width)?);
+        xobject.height(i32::try_from(image.height)?);
+        if image.gray {
+            xobject.color_space().device_gray();
+        } else {
+            xobject.color_space().device_rgb();
+        }
+        xobject.bits_per_component(8);
+        xobject.finish();
+
+        let mut content = Content::new();
+        content.save_state();
+        content.transform([width, 0.0, 0.0, height, x, y]);
+        content.x_object(image_name);
+        content.restore_state();
+        if let Some(footer) = footer {
+            // Centered inside the bottom margin
+            let size = 9.0;
+            Self::write_text(
+                &mut content,
+                &TextItem {
+                    text: footer.to_string(),
+                    size,
+                    x: (page_width - text_width(footer, size)) / 2.0,
+                    y: (margin - size) / 2.0,
+                    link: None,
+                },
+            );
+        }
+        self.pdf.stream(content_id, &content.finish());
+
+        let mut page = self.pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, page_width, page_height));
+        page.parent(self.page_tree_id);
+        page.contents(content_id);
+        let mut resources = page.resources();
+        resources.x_objects().pair(image_name, image_id);
+        if let Some(font_id) = font_id {
+            resources.fonts().pair(FONT_NAME, font_id);
+        }
+        resources.finish();
+        page.finish();
+
+        Ok(page_id)
+    }
+
+    /// Write a page of text into a page reserved with `alloc_page`.
+    pub fn write_text_page(&mut self, page_id: Ref, items: &[TextItem]) {
+        let content_id = self.alloc();
+        let font_id = self.font();
+        let (page_width, page_height) = self.page_dimensions();
+
+        let mut content = Content::new();
+        for item in items {
+            Self::write_text(&mut content, item);
+        }
+        self.pdf.stream(content_id, &content.finish());
+
+        let mut page = self.pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, page_width, page_height));
+        page.parent(self.page_tree_id);
+        page.contents(content_id);
+        page.resources().fonts().pair(FONT_NAME, font_id);
+        let mut annotations = page.annotations();
+        for item in items {
+            let Some(target) = item.link else {
+                continue;
+            };
+            let mut annotation = annotations.push();
+            annotation
+                .subtype(AnnotationType::Link)
+                .rect(Rect::new(
+                    item.x,
+                    item.y - item.size * 0.25,
+                    item.x + text_width(&item.text, item.size),
+                    item.y + item.size,
+                ))
+                .border(0.0, 0.0, 0.0, None);
+            annotation
+                .action()
+                .action_type(ActionType::GoTo)
+                .destination()
+                .page(target)
+                .fit();
+        }
+        annotations.finish();
+        page.finish();
+    }
+
+    /// Add a top-level bookmark to the document outline.
+    pub fn add_outline(&mut self, title: &str, page_id: Ref) {
+        self.outlines.push((title.to_string(), page_id));
+    }
+
+    fn write_outlines(&mut self) -> Option<Ref> {
+        if self.outlines.is_empty() {
+            return None;
+        }
+        let outline_id = self.alloc();
+        let item_ids = (0..self.outlines.len())
+            .map(|_| self.alloc())
+            .collect::<Vec<_>>();
+        self.pdf
+            .outline(outline_id)
+            .first(item_ids[0])
+            .last(item_ids[item_ids.len() - 1])
+            .count(i32::try_from(item_ids.len()).unwrap_or(i32::MAX));
+        for (idx, (title, page_id)) in self.outlines.iter().enumerate() {
+            let mut item = self.pdf.outline_item(item_ids[idx]);
+            item.title(TextStr(title)).parent(outline_id);
+            if idx > 0 {
+                item.prev(item_ids[idx - 1]);
+            }
+            if let Some(next) = item_ids.get(idx + 1) {
+                item.next(*next);
+            }
+            item.dest().page(*page_id).fit();
+        }
+        Some(outline_id)
+    }
+
+    pub fn finish(mut self, info: &DocumentInfo) -> Vec<u8> {
+        let outline_id = self.write_outlines();
+        let mut catalog = self.pdf.catalog(self.catalog_id);
+        catalog.pages(self.page_tree_id);
+        if let Some(outline_id) = outline_id {
+            catalog.outlines(outline_id);
+        }
+        catalog.finish();
+        self.pdf
+            .pages(self.page_tree_id)
+            .kids(self.page_ids.iter().copied())
+            .count(i32::try_from(self.page_ids.len()).unwrap_or(i32::MAX));
+
+        let info_id = self.alloc();
+        let mut document_info = self.pdf.document_info(info_id);
+        document_info.title(TextStr(&info.title));
+        if let Some(author) = &info.author {
+            document_info.author(TextStr(author));
+        }
+        document_info.subject(TextStr(&info.source));
+        document_info.creator(TextStr(env!("CARGO_PKG_NAME")));
+        document_info.finish();
+
+        self.pdf.finish()
+    }
+}
@@ -0,0 +1,243 @@
+use std::io::Write;
+
+use crate::numeric::to_usize;
+
+pub const BLOCKLIST_FILE: &str = "blocklist.json";
+
+// Side of the thumbnail compared by the hash, giving HASH_SIZE² bits
+const HASH_SIZE: u32 = 16;
+const HASH_WORDS: usize = 4;
+
+/// Difference hash of an image: which pixels of a 17x16 thumbnail are
+/// brighter than their right neighbor. It stays the same when the image is
+/// resized or recompressed, and its 256 bits tell apart the pages of a score
+/// that all look like white paper with black lines at a smaller size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageHash {
+    bits: [u64; HASH_WORDS],
+}
+
+impl ImageHash {
+    pub fn compute(binary: &[u8]) -> Option<ImageHash> {
+        let gray = image::load_from_memory(binary).ok()?.to_luma8();
+        Some(ImageHash::of_image(&gray))
+    }
+
+    fn of_image(gray: &image::GrayImage) -> ImageHash {
+        let filter = image::imageops::FilterType::Triangle;
+        let small = image::imageops::resize(gray, HASH_SIZE + 1, HASH_SIZE, filter);
+        let mut bits = [0u64; HASH_WORDS];
+        for y in 0..HASH_SIZE {
+            for x in 0..HASH_SIZE {
+                let left = small.get_pixel(x, y).0[0];
+                let right = small.get_pixel(x + 1, y).0[0];
+                let word = &mut bits[to_usize(y) / 4];
+                *word = *word << 1 | u64::from(left > right);
+            }
+        }
+        ImageHash { bits }
+    }
+
+    /// Number of differing bits, out of 256.
+    pub fn distance(&self, other: &ImageHash) -> u32 {
+        self.bits
+            .iter()
+            .zip(&other.bits)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+
+    pub fn to_hex(self) -> String {
+        let [a, b, c, d] = self.bits;
+        format!("{a:016x}{b:016x}{c:016x}{d:016x}")
+    }
+
+    /// Parse a hash written by `to_hex`. Hashes of an older, shorter kind
+    /// are not understood.
+    pub fn from_hex(hex: &str) -> Option<ImageHash> {
+        if hex.len() != HASH_WORDS * 16 {
+            return None;
+        }
+        let mut bits = [0u64; HASH_WORDS];
+        for (idx, word) in bits.iter_mut().enumerate() {
+            *word = u64::from_str_radix(hex.get(idx * 16..(idx + 1) * 16)?, 16).ok()?;
+        }
+        Some(ImageHash { bits })
+    }
+}
+
+struct SeenImage {
+    hash: ImageHash,
+    /// URLs of the articles the image was found in
+    articles: Vec<String>,
+}
+
+/// Images that were not taken as score pages, across the library. Such an
+/// image found in many articles is a banner of the account, and is skipped
+/// even when the classifier would take it.
+pub struct Blocklist {
+    path: String,
+    images: Vec<SeenImage>,
+}
+
+impl Blocklist {
+    /// Read the blocklist of the output folder, or start an empty one.
+    pub fn load(folder: &str) -> anyhow::Result<Blocklist> {
+        let path = format!("{folder}/{BLOCKLIST_FILE}");
+        let images = match std::fs::read_to_string(&path) {
+            Ok(content) => json::parse(&content)?["images"]
+                .members()
+                .filter_map(|image| {
+                    Some(SeenImage {
+                        hash: ImageHash::from_hex(image["hash"].as_str()?)?,
+                        articles: image["articles"]
+                            .members()
+                            .filter_map(json::JsonValue::as_str)
+                            .map(std::string::ToString::to_string)
+                            .collect(),
+                    })
+                })
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Blocklist { path, images })
+    }
+
+    /// Whether the image was seen in at least `min_articles` other articles.
+    pub fn is_blocked(
+        &self,
+        hash: &ImageHash,
+        article: &str,
+        min_articles: usize,
+        max_distance: u32,
+    ) -> bool {
+        min_articles > 0
+            && self.images.iter().any(|image| {
+                image.hash.distance(hash) <= max_distance
+                    && image.articles.iter().filter(|url| *url != article).count() >= min_articles
+            })
+    }
+
+    /// Remember that the image was found in the article and was not a page.
+    pub fn record(&mut self, hash: ImageHash, article: &str, max_distance: u32) {
+        match self
+            .images
+            .iter_mut()
+            .find(|image| image.hash.distance(&hash) <= max_distance)
+        {
+            Some(image) => {
+                if !image.articles.iter().any(|url| url == article) {
+                    image.articles.push(article.to_string());
+                }
+            }
+            None => self.images.push(SeenImage {
+                hash,
+                articles: vec![article.to_string()],
+            }),
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let images = self
+            .images
+            .iter()
+            .map(|image| {
+                json::object! {
+                    hash: image.hash.to_hex(),
+                    articles: image.articles.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut file = std::fs::File::create(&self.path)?;
+        file.write_all(json::object! { images: images }.pretty(2).as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A score page with the staves of one engraving template and notes
+    /// placed from the seed.
+    fn page(seed: u32) -> image::GrayImage {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            state >> 16
+        };
+        let mut page = image::GrayImage::from_pixel(600, 850, image::Luma([255]));
+        for staff in 0..8 {
+            let top = 80 + staff * 95;
+            for line in 0..5 {
+                for x in 40..560 {
+                    page.put_pixel(x, top + line * 10, image::Luma([0]));
+                }
+            }
+            for _ in 0..14 {
+                let (x, y) = (40 + next() % 510, top - 10 + next() % 60);
+                for dy in 0..8 {
+                    for dx in 0..10 {
+                        page.put_pixel(x + dx, y + dy, image::Luma([0]));
+                    }
+                }
+            }
+        }
+        page
+    }
+
+    fn hash(gray: &image::GrayImage) -> ImageHash {
+        ImageHash::of_image(gray)
+    }
+
+    #[test]
+    fn resized_image_is_the_same() {
+        let original = page(1);
+        let smaller =
+            image::imageops::resize(&original, 450, 638, image::imageops::FilterType::Triangle);
+        let distance = hash(&original).distance(&hash(&smaller));
+        assert!(distance <= 12, "distance {distance}");
+    }
+
+    #[test]
+    fn pages_of_one_template_differ() {
+        let hashes = (1..6).map(|seed| hash(&page(seed))).collect::<Vec<_>>();
+        for (idx, a) in hashes.iter().enumerate() {
+            for b in &hashes[idx + 1..] {
+                assert!(a.distance(b) > 12, "distance {}", a.distance(b));
+            }
+        }
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let hash = hash(&page(3));
+        assert_eq!(ImageHash::from_hex(&hash.to_hex()), Some(hash));
+        assert_eq!(
+            ImageHash::from_hex("00ff00ff00ff00ff00ff00ff00ff00ff"),
+            None
+        );
+    }
+
+    #[test]
+    fn blocklist_learns_recurring_images() {
+        let mut blocklist = Blocklist {
+            path: String::new(),
+            images: Vec::new(),
+        };
+        let banner = hash(&page(7));
+        let other = hash(&page(8));
+        for article in ["a", "b"] {
+            blocklist.record(banner, article, 12);
+        }
+        assert!(!blocklist.is_blocked(&banner, "c", 3, 12));
+        blocklist.record(banner, "b", 12);
+        blocklist.record(banner, "c", 12);
+        assert!(blocklist.is_blocked(&banner, "d", 3, 12));
+        // The articles the image was found in don't count for themselves
+        assert!(!blocklist.is_blocked(&banner, "c", 3, 12));
+        assert!(!blocklist.is_blocked(&other, "d", 3, 12));
+        assert!(!blocklist.is_blocked(&banner, "d", 0, 12));
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+};
+
+// One syllable per line, followed by the characters read that way
+const TABLE: &str = include_str!("pinyin.txt");
+const MAX_SYLLABLE_LEN: usize = 6;
+// Initials written with two letters
+const DIGRAPHS: [&str; 3] = ["zh", "ch", "sh"];
+
+static READINGS: LazyLock<HashMap<char, &'static str>> = LazyLock::new(|| {
+    TABLE
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(' '))
+        .flat_map(|(syllable, chars)| chars.chars().map(move |c| (c, syllable)))
+        .collect()
+});
+
+static SYLLABLES: LazyLock<HashSet<&'static str>> =
+    LazyLock::new(|| READINGS.values().copied().collect());
+
+/// Turn text into tokens: the syllable of every Chinese character and the
+/// other words in lower case. Characters with several readings get the most
+/// common one.
+pub fn tokens(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in text.chars() {
+        if let Some(syllable) = READINGS.get(&c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            tokens.push((*syllable).to_string());
+        } else if c.is_alphanumeric() {
+            word.extend(c.to_lowercase());
+        } else if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// The initial of the syllable of every Chinese character: its first letter,
+/// or `zh`, `ch` and `sh`.
+pub fn initials(text: &str) -> Vec<String> {
+    text.chars()
+        .filter_map(|c| READINGS.get(&c))
+        .filter_map(|syllable| {
+            DIGRAPHS
+                .iter()
+                .find(|digraph| syllable.starts_with(*digraph))
+                .map(|digraph| (*digraph).to_string())
+                .or_else(|| syllable.get(..1).map(str::to_string))
+        })
+        .collect()
+}
+
+/// Split typed initials such as `zhehd` into the initials of [`initials`].
+pub fn split_initials(letters: &str) -> Vec<&str> {
+    let mut initials = Vec::new();
+    let mut start = 0;
+    while start < letters.len() {
+        let size = if DIGRAPHS
+            .iter()
+            .any(|digraph| letters[start..].starts_with(digraph))
+        {
+            2
+        } else {
+            letters[start..].chars().next().map_or(1, char::len_utf8)
+        };
+        initials.push(&letters[start..start + size]);
+        start += size;
+    }
+    initials
+}
+
+fn is_syllable_prefix(letters: &str) -> bool {
+    SYLLABLES
+        .iter()
+        .any(|syllable| syllable.starts_with(letters))
+}
+
+/// Split letters such as `zhuenhaoda` into syllables, preferring the longest
+/// ones. The last syllable may be incomplete.
+pub fn split(letters: &str) -> Option<Vec<&str>> {
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_lowercase()) {
+        return None;
+    }
+    // Whether the letters from each offset on can be split, from the end
+    let len = letters.len();
+    let mut splittable = vec![false; len + 1];
+    splittable[len] = true;
+    for start in (0..len).rev() {
+        splittable[start] = (1..=MAX_SYLLABLE_LEN.min(len - start)).any(|size| {
+            SYLLABLES.contains(&letters[start..start + size]) && splittable[start + size]
+        }) || is_syllable_prefix(&letters[start..]);
+    }
+    if !splittable[0] {
+        return None;
+    }
+
+    let mut syllables = Vec::new();
+    let mut start = 0;
+    while start < len {
+        let size = (1..=MAX_SYLLABLE_LEN.min(len - start)).rev().find(|size| {
+            SYLLABLES.contains(&letters[start..start + size]) && splittable[start + size]
+        });
+        if let Some(size) = size {
+            syllables.push(&letters[start..start + size]);
+            start += size;
+        } else {
+            // An incomplete syllable is only allowed at the end
+            syllables.push(&letters[start..]);
+            start = len;
+        }
+    }
+    Some(syllables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_syllables() {
+        assert_eq!(split("zhuenhaoda"), Some(vec!["zhu", "en", "hao", "da"]));
+        assert_eq!(split("zhuenha"), Some(vec!["zhu", "en", "ha"]));
+        assert_eq!(split("qiyiendi"), Some(vec!["qi", "yi", "en", "di"]));
+        assert_eq!(split("qiyiendian"), Some(vec!["qi", "yi", "en", "dian"]));
+        assert_eq!(split("Zhu"), None);
+    }
+
+    #[test]
+    fn initials_with_digraphs() {
+        assert_eq!(initials("主恩浩大"), vec!["zh", "e", "h", "d"]);
+        assert_eq!(initials("奇异恩典"), vec!["q", "y", "e", "d"]);
+        assert_eq!(split_initials("zhehd"), vec!["zh", "e", "h", "d"]);
+        assert_eq!(split_initials("zehd"), vec!["z", "e", "h", "d"]);
+    }
+
+    #[test]
+    fn split_long_input_that_cannot_be_split() {
+        let letters = format!("{}v", "xian".repeat(100));
+        let started = std::time::Instant::now();
+        assert_eq!(split(&letters), None);
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+}
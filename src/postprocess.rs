@@ -0,0 +1,173 @@
+use image::{GrayImage, Luma};
+
+use crate::{
+    config::PostProcessOptions,
+    numeric::{count_to_f64, to_f64, to_u32, to_usize},
+};
+
+// Pixels darker than this count as ink when measuring the skew
+const INK_LUMA: u8 = 128;
+// The skew is measured on a copy of at most this width
+const SKEW_SAMPLE_WIDTH: u32 = 800;
+const SKEW_STEP_DEGREES: f64 = 0.1;
+// White space kept around the content when cropping
+const CROP_PADDING: u32 = 16;
+
+/// Find the rotation in degrees that makes the ink rows the sharpest,
+/// which for a score means the staff lines are horizontal.
+fn estimate_skew(gray: &GrayImage, max_degrees: f64) -> f64 {
+    let sample = if gray.width() > SKEW_SAMPLE_WIDTH {
+        let height = to_u32(
+            f64::from(gray.height()) * f64::from(SKEW_SAMPLE_WIDTH) / f64::from(gray.width()),
+        );
+        image::imageops::resize(
+            gray,
+            SKEW_SAMPLE_WIDTH,
+            height.max(1),
+            image::imageops::FilterType::Triangle,
+        )
+    } else {
+        gray.clone()
+    };
+    let ink = sample
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p.0[0] < INK_LUMA)
+        .map(|(x, y, _)| (f64::from(x), f64::from(y)))
+        .collect::<Vec<_>>();
+    if ink.is_empty() {
+        return 0.0;
+    }
+
+    let steps = to_u32(max_degrees / SKEW_STEP_DEGREES);
+    let height = f64::from(sample.height());
+    let width = f64::from(sample.width());
+    let mut best = (0.0, 0u64);
+    for step in 0..=steps * 2 {
+        let degrees = f64::from(step) * SKEW_STEP_DEGREES - f64::from(steps) * SKEW_STEP_DEGREES;
+        let slope = degrees.to_radians().tan();
+        // Project the ink onto rows as if the page were sheared back by the angle
+        let offset = width * slope.abs();
+        let mut rows = vec![0u64; to_usize(to_u32(height + 2.0 * offset + 1.0))];
+        for (x, y) in &ink {
+            let row = to_usize(to_u32(y - x * slope + offset));
+            if let Some(count) = rows.get_mut(row) {
+                *count += 1;
+            }
+        }
+        let sharpness = rows.iter().map(|count| count * count).sum::<u64>();
+        if sharpness > best.1 {
+            best = (degrees, sharpness);
+        }
+    }
+    best.0
+}
+
+/// Rotate the image around its center, filling the uncovered corners with white.
+fn rotate(gray: &GrayImage, degrees: f64) -> GrayImage {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (width, height) = gray.dimensions();
+    let (cx, cy) = (f64::from(width) / 2.0, f64::from(height) / 2.0);
+    GrayImage::from_fn(width, height, |x, y| {
+        // Map every target pixel back into the source
+        let (dx, dy) = (f64::from(x) - cx, f64::from(y) - cy);
+        let sx = cos * dx - sin * dy + cx;
+        let sy = sin * dx + cos * dy + cy;
+        if sx < 0.0 || sy < 0.0 || sx >= f64::from(width - 1) || sy >= f64::from(height - 1) {
+            return Luma([255]);
+        }
+        let (x0, y0) = (sx.floor(), sy.floor());
+        let (fx, fy) = (sx - x0, sy - y0);
+        let (x0, y0) = (to_u32(x0), to_u32(y0));
+        let get = |x, y| f64::from(gray.get_pixel(x, y).0[0]);
+        let top = get(x0, y0) * (1.0 - fx) + get(x0 + 1, y0) * fx;
+        let bottom = get(x0, y0 + 1) * (1.0 - fx) + get(x0 + 1, y0 + 1) * fx;
+        Luma([u8::try_from(to_u32(top * (1.0 - fy) + bottom * fy)).unwrap_or(u8::MAX)])
+    })
+}
+
+/// Adaptive threshold: a pixel is ink if it is clearly darker than its
+/// neighborhood and dark enough overall, which drops grey backgrounds and
+/// light watermarks.
+fn binarize(gray: &GrayImage, options: &PostProcessOptions) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let (w, h) = (to_usize(width), to_usize(height));
+
+    // Integral image of the luma
+    let mut integral = vec![0u64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row_sum = 0u64;
+        for x in 0..w {
+            row_sum += u64::from(gray.as_raw()[y * w + x]);
+            integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row_sum;
+        }
+    }
+
+    let half = to_usize((options.threshold_window / 2).max(1));
+    GrayImage::from_fn(width, height, |px, py| {
+        let (x, y) = (to_usize(px), to_usize(py));
+        let (x0, y0) = (x.saturating_sub(half), y.saturating_sub(half));
+        let (x1, y1) = ((x + half + 1).min(w), (y + half + 1).min(h));
+        let sum = integral[y1 * (w + 1) + x1] + integral[y0 * (w + 1) + x0]
+            - integral[y0 * (w + 1) + x1]
+            - integral[y1 * (w + 1) + x0];
+        let mean = to_f64(sum) / count_to_f64((x1 - x0) * (y1 - y0));
+        let pixel = f64::from(gray.get_pixel(px, py).0[0]);
+        let ink = pixel < mean * (1.0 - options.threshold_ratio)
+            && pixel < f64::from(options.max_ink_luma);
+        Luma([if ink { 0 } else { 255 }])
+    })
+}
+
+/// Trim the borders that have the same color as the background.
+fn crop(gray: &GrayImage) -> GrayImage {
+    // The background is the brightest common color
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[usize::from(pixel.0[0])] += 1;
+    }
+    let background = (0..=255u8)
+        .max_by_key(|luma| histogram[usize::from(*luma)] * (u64::from(*luma) + 1))
+        .unwrap_or(255);
+    let is_content = |p: &Luma<u8>| p.0[0].abs_diff(background) > 40;
+
+    let (width, height) = gray.dimensions();
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in gray.enumerate_pixels() {
+        if is_content(pixel) {
+            bounds = Some(match bounds {
+                Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                None => (x, y, x, y),
+            });
+        }
+    }
+    let Some((x0, y0, x1, y1)) = bounds else {
+        return gray.clone();
+    };
+    let x0 = x0.saturating_sub(CROP_PADDING);
+    let y0 = y0.saturating_sub(CROP_PADDING);
+    let x1 = (x1 + CROP_PADDING).min(width - 1);
+    let y1 = (y1 + CROP_PADDING).min(height - 1);
+    image::imageops::crop_imm(gray, x0, y0, x1 - x0 + 1, y1 - y0 + 1).to_image()
+}
+
+/// Clean up a sheet page and return it encoded as PNG.
+pub fn process(binary: &[u8], options: &PostProcessOptions) -> anyhow::Result<Vec<u8>> {
+    let mut gray = image::load_from_memory(binary)?.to_luma8();
+    if options.deskew {
+        let degrees = estimate_skew(&gray, options.max_skew_degrees);
+        if degrees.abs() >= SKEW_STEP_DEGREES {
+            log::info!("Deskewing by {degrees:.1} degrees");
+            gray = rotate(&gray, degrees);
+        }
+    }
+    if options.binarize {
+        gray = binarize(&gray, options);
+    }
+    if options.crop {
+        gray = crop(&gray);
+    }
+
+    let mut png = std::io::Cursor::new(Vec::new());
+    gray.write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
@@ -0,0 +1,89 @@
+use std::sync::LazyLock;
+
+use crate::pinyin;
+
+static JIEBA: LazyLock<jieba_rs::Jieba> = LazyLock::new(jieba_rs::Jieba::new);
+
+// Initials of fewer letters than this match too many songs
+const MIN_INITIALS: usize = 2;
+
+/// The columns of a song in the full-text index, as space separated tokens.
+pub struct Document {
+    pub title: String,
+    pub lyrics: String,
+    pub title_pinyin: String,
+    pub lyrics_pinyin: String,
+    pub title_initials: String,
+    pub lyrics_initials: String,
+}
+
+/// Cut Chinese text into words, with the shorter words that longer ones contain.
+fn segment(text: &str) -> String {
+    JIEBA
+        .cut_for_search(text, true)
+        .into_iter()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Document {
+    pub fn new(title: &str, lyrics: &str) -> Document {
+        Document {
+            title: segment(title),
+            lyrics: segment(lyrics),
+            title_pinyin: pinyin::tokens(title).join(" "),
+            lyrics_pinyin: pinyin::tokens(lyrics).join(" "),
+            title_initials: pinyin::initials(title).join(" "),
+            lyrics_initials: pinyin::initials(lyrics).join(" "),
+        }
+    }
+}
+
+fn quote(token: &str) -> String {
+    format!("\"{}\"", token.replace('"', "\"\""))
+}
+
+/// Build the FTS5 query of a search: the words of the text, and if it is only
+/// made of letters, the pinyin syllables or initials they may spell.
+pub fn expression(text: &str) -> Option<String> {
+    let words = JIEBA
+        .cut(text, true)
+        .into_iter()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| quote(&word.to_lowercase()))
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        return None;
+    }
+    let mut alternatives = vec![format!("{{title lyrics}} : ({})", words.join(" AND "))];
+
+    let letters = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    if let Some(syllables) = pinyin::split(&letters) {
+        // The last syllable may still be typed, as in `zhuenha`
+        alternatives.push(format!(
+            "{{title_pinyin lyrics_pinyin}} : {} *",
+            quote(&syllables.join(" "))
+        ));
+    }
+    if letters.len() >= MIN_INITIALS && letters.chars().all(|c| c.is_ascii_lowercase()) {
+        // A lone z, c or s also stands for zh, ch or sh, as in `zehd`
+        let initials = pinyin::split_initials(&letters)
+            .into_iter()
+            .map(|initial| match initial {
+                "z" | "c" | "s" => format!("{} *", quote(initial)),
+                _ => quote(initial),
+            })
+            .collect::<Vec<_>>();
+        alternatives.push(format!(
+            "{{title_initials lyrics_initials}} : {}",
+            initials.join(" + ")
+        ));
+    }
+    Some(alternatives.join(" OR "))
+}
@@ -0,0 +1,355 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{catalog::Catalog, config, jobs};
+
+const INDEX_HTML: &str = include_str!("server.html");
+// Largest part of a file sent for a range request without an end, enough for
+// players to start while they seek through long videos
+const MAX_RANGE_LEN: u64 = 8 * 1024 * 1024;
+
+#[derive(Clone)]
+struct Server {
+    output: String,
+    token: Option<String>,
+    queue: Arc<jobs::Queue>,
+}
+
+fn json_response(status: StatusCode, value: &json::JsonValue) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/json; charset=utf-8")],
+        value.dump(),
+    )
+        .into_response()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    json_response(status, &json::object! { error: message })
+}
+
+/// Compare a sent token with the configured one in a time that does not
+/// tell how much of it is right.
+fn same_token(sent: &[u8], token: &[u8]) -> bool {
+    sent.len() == token.len()
+        && sent
+            .iter()
+            .zip(token)
+            .fold(0, |diff, (sent, token)| diff | (sent ^ token))
+            == 0
+}
+
+impl Server {
+    /// Check the token of a request to the job API, and return the answer
+    /// to send instead if it is not allowed.
+    fn reject(&self, headers: &HeaderMap) -> Option<Response> {
+        let Some(token) = &self.token else {
+            return Some(error_response(
+                StatusCode::FORBIDDEN,
+                "The job API is disabled, set server.token in the config",
+            ));
+        };
+        let sent = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let allowed = sent.is_some_and(|sent| same_token(sent.trim().as_bytes(), token.as_bytes()));
+        (!allowed).then(|| error_response(StatusCode::UNAUTHORIZED, "Invalid token"))
+    }
+
+    /// Run a query on the catalog away from the async workers.
+    async fn with_catalog<F>(&self, query: F) -> Response
+    where
+        F: FnOnce(&Catalog) -> anyhow::Result<Option<json::JsonValue>> + Send + 'static,
+    {
+        let output = self.output.clone();
+        let result = tokio::task::spawn_blocking(move || query(&Catalog::open(&output)?)).await;
+        match result {
+            Ok(Ok(Some(value))) => json_response(StatusCode::OK, &value),
+            Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, "No such song"),
+            Ok(Err(e)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        }
+    }
+}
+
+async fn index() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}
+
+/// Every song of the catalog, or those matching `q`.
+async fn list_songs(
+    State(server): State<Server>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let query = params
+        .get("q")
+        .map(|query| query.trim().to_string())
+        .unwrap_or_default();
+    server
+        .with_catalog(move |catalog| {
+            let summaries = if query.is_empty() {
+                catalog.list()?
+            } else {
+                catalog.search(&query)?
+            };
+            Ok(Some(json::JsonValue::from(
+                summaries
+                    .iter()
+                    .map(crate::catalog::Summary::to_json)
+                    .collect::<Vec<_>>(),
+            )))
+        })
+        .await
+}
+
+async fn get_song(State(server): State<Server>, Path(folder): Path<String>) -> Response {
+    server
+        .with_catalog(move |catalog| Ok(catalog.get(&folder)?.map(|record| record.to_json())))
+        .await
+}
+
+/// Queue an article, given as `{"url": "...", "video_url": "..."}`.
+async fn submit_job(State(server): State<Server>, headers: HeaderMap, body: String) -> Response {
+    if let Some(response) = server.reject(&headers) {
+        return response;
+    }
+    let Ok(request) = json::parse(&body) else {
+        return error_response(StatusCode::BAD_REQUEST, "The body is not JSON");
+    };
+    let is_http = |url: &str| url.starts_with("https://") || url.starts_with("http://");
+    let Some(url) = request["url"]
+        .as_str()
+        .map(str::trim)
+        .filter(|url| is_http(url))
+    else {
+        return error_response(StatusCode::BAD_REQUEST, "url must be an HTTP URL");
+    };
+    let video_url = request["video_url"]
+        .as_str()
+        .map(str::trim)
+        .filter(|url| !url.is_empty());
+    if video_url.is_some_and(|url| !is_http(url)) {
+        return error_response(StatusCode::BAD_REQUEST, "video_url must be an HTTP URL");
+    }
+    match server
+        .queue
+        .submit(url.to_string(), video_url.map(str::to_string))
+    {
+        Ok(job) => json_response(StatusCode::ACCEPTED, &job),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+async fn list_jobs(State(server): State<Server>, headers: HeaderMap) -> Response {
+    if let Some(response) = server.reject(&headers) {
+        return response;
+    }
+    json_response(StatusCode::OK, &json::JsonValue::from(server.queue.list()))
+}
+
+/// The state of a job, with the catalog entry of its song once it is done.
+async fn get_job(
+    State(server): State<Server>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Response {
+    if let Some(response) = server.reject(&headers) {
+        return response;
+    }
+    let Some((mut job, folder)) = server.queue.get(id) else {
+        return error_response(StatusCode::NOT_FOUND, "No such job");
+    };
+    if job["state"] == "done" {
+        if let Some(folder) = folder {
+            let output = server.output.clone();
+            let song =
+                tokio::task::spawn_blocking(move || Catalog::open(&output)?.get(&folder)).await;
+            if let Ok(Ok(Some(song))) = song {
+                job["song"] = song.to_json();
+            }
+        }
+    }
+    json_response(StatusCode::OK, &job)
+}
+
+fn content_type(path: &std::path::Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "pdf" => "application/pdf",
+        "json" => "application/json; charset=utf-8",
+        "xml" | "musicxml" => "application/xml",
+        "mxl" => "application/vnd.recordare.musicxml",
+        "txt" | "" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The part of a file asked for by the `Range` header of a request.
+enum Range {
+    Whole,
+    Part(u64, u64),
+    /// A range outside of the file
+    Unsatisfiable,
+}
+
+/// Parse a `bytes=start-end` range of a file of `len` bytes. Ranges that
+/// can't be parsed are ignored, as browsers do.
+fn parse_range(headers: &HeaderMap, len: u64) -> Range {
+    let Some(range) = headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split_once('-'))
+    else {
+        return Range::Whole;
+    };
+    let Some(last) = len.checked_sub(1) else {
+        return Range::Unsatisfiable;
+    };
+    let (start, end) = if range.0.is_empty() {
+        // The last bytes of the file
+        let Ok(suffix) = range.1.parse::<u64>() else {
+            return Range::Whole;
+        };
+        if suffix == 0 {
+            return Range::Unsatisfiable;
+        }
+        (len - suffix.min(len), last)
+    } else {
+        let Ok(start) = range.0.parse::<u64>() else {
+            return Range::Whole;
+        };
+        let end = match range.1.parse::<u64>() {
+            Ok(end) => end.min(last),
+            Err(_) => start.saturating_add(MAX_RANGE_LEN - 1).min(last),
+        };
+        (start, end)
+    };
+    if start > last {
+        Range::Unsatisfiable
+    } else if start > end {
+        Range::Whole
+    } else {
+        Range::Part(start, end)
+    }
+}
+
+/// Send a file of the output folder, in parts if the player asks for a range.
+async fn get_file(
+    State(server): State<Server>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let relative = std::path::Path::new(&path);
+    if relative
+        .components()
+        .any(|component| !matches!(component, std::path::Component::Normal(_)))
+    {
+        return error_response(StatusCode::BAD_REQUEST, "Invalid path");
+    }
+    let path = std::path::Path::new(&server.output).join(relative);
+    let Ok(mut file) = tokio::fs::File::open(&path).await else {
+        return error_response(StatusCode::NOT_FOUND, "No such file");
+    };
+    let result = async {
+        let len = file.metadata().await?.len();
+        let mut response_headers = vec![
+            (header::CONTENT_TYPE, content_type(&path).to_string()),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+        ];
+        let (status, body) = match parse_range(&headers, len) {
+            Range::Whole => {
+                response_headers.push((header::CONTENT_LENGTH, len.to_string()));
+                (StatusCode::OK, Body::from_stream(ReaderStream::new(file)))
+            }
+            Range::Part(start, end) => {
+                file.seek(std::io::SeekFrom::Start(start)).await?;
+                response_headers.push((header::CONTENT_LENGTH, (end - start + 1).to_string()));
+                response_headers
+                    .push((header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}")));
+                let part = ReaderStream::new(file.take(end - start + 1));
+                (StatusCode::PARTIAL_CONTENT, Body::from_stream(part))
+            }
+            Range::Unsatisfiable => {
+                response_headers.push((header::CONTENT_RANGE, format!("bytes */{len}")));
+                (StatusCode::RANGE_NOT_SATISFIABLE, Body::empty())
+            }
+        };
+        anyhow::Ok((status, response_headers, body))
+    }
+    .await;
+    match result {
+        Ok((status, response_headers, body)) => {
+            let mut response = (status, body).into_response();
+            for (name, value) in response_headers {
+                if let Ok(value) = value.parse() {
+                    response.headers_mut().insert(name, value);
+                }
+            }
+            response
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+/// Serve the song library of the output folder to browsers on `address`, and
+/// run the jobs submitted to the API with the config.
+pub async fn serve(output: &str, address: &str, config: config::Config) -> anyhow::Result<()> {
+    let token = config.server.token.clone();
+    if token.is_none() {
+        log::warn!("No server.token in the config, the job API is disabled");
+    }
+    let queue = jobs::Queue::start(config, output)?;
+    let app = Router::new()
+        .route("/", get(index))
+        .route("/api/songs", get(list_songs))
+        .route("/api/songs/{*folder}", get(get_song))
+        .route("/api/jobs", get(list_jobs).post(submit_job))
+        .route("/api/jobs/{id}", get(get_job))
+        .route("/files/{*path}", get(get_file))
+        .with_state(Server {
+            output: output.to_string(),
+            token,
+            queue,
+        });
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    log::info!("Serving {output} on http://{address}");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_tokens() {
+        assert!(same_token(b"secret", b"secret"));
+        assert!(!same_token(b"secreT", b"secret"));
+        assert!(!same_token(b"secret!", b"secret"));
+        assert!(!same_token(b"", b"secret"));
+    }
+}
@@ -1,35 +1,72 @@
-use std::io::Write;
+use std::{collections::HashSet, io::Write};
 
 use crate::{
-    errors,
+    audio, catalog, classifier, config, errors, image_format, jobs, layout, lyrics, manifest,
+    metadata, naming, omr, paths, pdf, phash, postprocess, title,
     video::{Downloader, Downloader20251220},
 };
 
+const ORIGINAL_FOLDER: &str = "original";
+const ACCOMPANIMENT_KEYWORDS: [&str; 8] = [
+    "伴奏",
+    "伴唱",
+    "纯音乐",
+    "純音樂",
+    "accompaniment",
+    "instrumental",
+    "karaoke",
+    "mr",
+];
+
+pub struct Audio {
+    name: String,
+    url: String,
+    accompaniment: bool,
+}
+
+pub struct SheetImage {
+    url: String,
+    /// Position of the image in the article, from the `#imgIndex=` fragment
+    img_index: Option<u32>,
+}
+
 pub struct Sheet {
     url: String,
     title: String,
-    accompaniment: String,
+    /// Named parts of the title, such as `song` and `artist`
+    title_parts: std::collections::BTreeMap<String, String>,
+    account: Option<String>,
+    cover: Option<String>,
+    audios: Vec<Audio>,
     video: Option<String>,
-    sheets: Vec<String>,
+    sheets: Vec<SheetImage>,
+    /// `imgIndex` of the images of the article that are not sheet candidates
+    rejected_img_indexes: Vec<u32>,
+    profile: config::Profile,
+    author: Option<String>,
+    /// Publish time of the article in seconds
+    publish_time: Option<i64>,
+    description: Option<String>,
+    /// Text of the article, one paragraph per line
+    body: Option<String>,
+    output: config::OutputOptions,
+    pdf: config::PdfOptions,
+    omr: config::OmrOptions,
+    progress: jobs::Progress,
+    /// Whether an article without a video URL is downloaded without it
+    video_optional: bool,
 }
 
 use thirtyfour::prelude::*;
 
 impl Sheet {
-    fn get_png_dimensions(binary: &[u8]) -> Option<(u32, u32)> {
-        const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
-        if binary.len() < 24 || binary[..8] != PNG_SIGNATURE {
-            return None;
-        }
-
-        let width = u32::from_be_bytes(binary[16..20].try_into().ok()?);
-        let height = u32::from_be_bytes(binary[20..24].try_into().ok()?);
-        Some((width, height))
-    }
-
-    fn is_likely_sheet_png(binary: &[u8]) -> bool {
-        Self::get_png_dimensions(binary)
-            .is_some_and(|(width, height)| width >= 500 && height >= 500)
+    /// Return the image format if the content passes the image rules of the profile.
+    fn get_sheet_image_format(&self, binary: &[u8]) -> Option<image_format::ImageFormat> {
+        image_format::get_dimensions(binary)
+            .filter(|(format, width, height)| {
+                self.profile.image.matches_image(*format, *width, *height)
+            })
+            .map(|(format, _, _)| format)
     }
 
     fn get_image_url(img: scraper::element_ref::ElementRef<'_>) -> Option<String> {
@@ -40,14 +77,389 @@ impl Sheet {
             .map(std::string::ToString::to_string)
     }
 
-    fn is_sheet_image(url: &str) -> bool {
-        url.contains("mmbiz.qpic.cn")
-            && url.contains("wx_fmt=png")
-            && url.contains("from=appmsg")
-            && url.contains("#imgIndex=")
+    fn get_audio_name(audio: scraper::element_ref::ElementRef<'_>) -> String {
+        ["name", "title"]
+            .into_iter()
+            .find_map(|attr| audio.value().attr(attr))
+            .map(|name| name.split_whitespace().collect::<Vec<_>>().join(" "))
+            .unwrap_or_default()
+    }
+
+    fn is_accompaniment_name(name: &str) -> bool {
+        let name = name.to_lowercase();
+        ACCOMPANIMENT_KEYWORDS.iter().any(|keyword| {
+            if keyword.is_ascii() {
+                // Short latin keywords like "mr" must match a whole word
+                name.split(|c: char| !c.is_ascii_alphanumeric())
+                    .any(|word| word == *keyword)
+            } else {
+                name.contains(keyword)
+            }
+        })
+    }
+
+    /// Write the lyrics found in the article and the metadata sidecar.
+    fn write_metadata(&self, path: &str) -> anyhow::Result<()> {
+        let titles = self
+            .title_parts
+            .values()
+            .chain([&self.title])
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let lyrics::Lyrics { lines, annotations } = self
+            .body
+            .as_deref()
+            .map(|body| lyrics::extract(body, &titles))
+            .unwrap_or_default();
+        if !annotations.is_empty() {
+            log::info!(
+                "Parsed key {:?}, meter {:?}, tempo {:?}",
+                annotations.key,
+                annotations.meter,
+                annotations.tempo
+            );
+        }
+        let lyrics_file = if lines.is_empty() {
+            None
+        } else {
+            log::info!("Writing {} lyric lines...", lines.len());
+            let mut file = std::fs::File::create(format!("{path}/{}", lyrics::LYRICS_FILE))?;
+            file.write_all(lines.join("\n").as_bytes())?;
+            Some(lyrics::LYRICS_FILE.to_string())
+        };
+        metadata::Metadata {
+            url: self.url.clone(),
+            title: self.title.clone(),
+            title_parts: self.title_parts.clone(),
+            account: self.account.clone(),
+            author: self.author.clone(),
+            publish_time: self.publish_time,
+            description: self.description.clone(),
+            cover: self.cover.clone(),
+            body: self.body.clone(),
+            annotations,
+            lyrics: lyrics_file,
+        }
+        .save(path)
+    }
+
+    /// The naming templates filled with the values of this article.
+    fn naming(&self) -> naming::Naming<'_> {
+        let mut values = self.title_parts.clone();
+        values.insert("title".to_string(), self.title.clone());
+        let optional = [
+            ("account", &self.account),
+            ("author", &self.author),
+            ("description", &self.description),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                values.insert(key.to_string(), value.clone());
+            }
+        }
+        naming::Naming::new(&self.output, values, self.publish_time)
+    }
+
+    fn get_audio_file_names(&self) -> Vec<String> {
+        let naming = self.naming();
+        let accompaniment = naming.accompaniment();
+        let mut used = std::collections::HashSet::new();
+        used.insert(accompaniment.clone());
+        self.audios
+            .iter()
+            .enumerate()
+            .map(|(idx, audio)| {
+                if audio.accompaniment {
+                    return accompaniment.clone();
+                }
+                let stem = naming.audio(&audio.name, idx + 1);
+                let mut file_name = format!("{stem}.mp3");
+                let mut suffix = 2;
+                while !used.insert(file_name.clone()) {
+                    file_name = format!("{stem} ({suffix}).mp3");
+                    suffix += 1;
+                }
+                file_name
+            })
+            .collect()
+    }
+
+    fn get_audios(document: &scraper::Html) -> anyhow::Result<Vec<Audio>> {
+        // Get the attr voice_encode_fileid and the label of every mpvoice
+        let selector = scraper::Selector::parse("mp-common-mpaudio")
+            .map_err(|_| errors::SheetError::ParseFailed)?;
+        let mut audios = document
+            .select(&selector)
+            .filter_map(|audio| {
+                let voice_id = audio.value().attr("voice_encode_fileid")?;
+                Some(Audio {
+                    name: Self::get_audio_name(audio),
+                    url: format!("https://res.wx.qq.com/voice/getvoice?mediaid={voice_id}"),
+                    accompaniment: false,
+                })
+            })
+            .collect::<Vec<_>>();
+        // Pick the accompaniment by its label, otherwise assume the first track is
+        let accompaniment_idx = audios
+            .iter()
+            .position(|audio| Self::is_accompaniment_name(&audio.name))
+            .unwrap_or(0);
+        audios
+            .get_mut(accompaniment_idx)
+            .ok_or(errors::SheetError::GetFailed(
+                "accompaniment url".to_string(),
+            ))?
+            .accompaniment = true;
+        for audio in &audios {
+            log::info!(
+                "Parsed voice URL: {} ({}{})",
+                audio.url,
+                audio.name,
+                if audio.accompaniment {
+                    ", accompaniment"
+                } else {
+                    ""
+                }
+            );
+        }
+        Ok(audios)
+    }
+
+    /// Read a string variable that Weixin embeds in the page scripts,
+    /// e.g. `var nickname = htmlDecode("...");`
+    fn get_js_var(html: &str, name: &str) -> Option<String> {
+        let re = regex::Regex::new(&format!(
+            r#"(?:var\s+)?\b{name}\s*[=:]\s*(?:htmlDecode\()?\s*["']([^"']*)["']"#
+        ))
+        .ok()?;
+        re.captures(html)
+            .map(|caps| caps[1].trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn get_selector_text(document: &scraper::Html, selector: &str) -> Option<String> {
+        let selector = scraper::Selector::parse(selector).ok()?;
+        document
+            .select(&selector)
+            .map(|element| {
+                element
+                    .text()
+                    .collect::<String>()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .find(|text| !text.is_empty())
+    }
+
+    fn get_meta_content(document: &scraper::Html, property: &str) -> Option<String> {
+        let selector = scraper::Selector::parse(&format!(
+            r#"meta[property="{property}"], meta[name="{property}"]"#
+        ))
+        .ok()?;
+        document
+            .select(&selector)
+            .find_map(|meta| meta.value().attr("content"))
+            .map(str::trim)
+            .filter(|content| !content.is_empty())
+            .map(std::string::ToString::to_string)
+    }
+
+    /// The title from the heading of the article if there is one, or else
+    /// from the metadata of the page.
+    fn get_raw_title(html: &str, document: &scraper::Html) -> Option<String> {
+        Self::get_selector_text(document, "h1")
+            .or_else(|| Self::get_meta_content(document, "og:title"))
+            .or_else(|| Self::get_js_var(html, "msg_title"))
+            .or_else(|| Self::get_selector_text(document, "title"))
     }
 
-    pub async fn try_new(url: String, index: usize) -> anyhow::Result<Sheet> {
+    fn get_wx_fmt(url: &str) -> Option<&str> {
+        url.split(['?', '&', '#'])
+            .find_map(|param| param.strip_prefix("wx_fmt="))
+    }
+
+    /// Rewrite an mmbiz URL to ask for the original resolution and format, e.g.
+    /// `.../mmbiz_png/<id>/640?wx_fmt=png&tp=webp&wxfrom=5` into `.../mmbiz_png/<id>/0?wx_fmt=png`
+    fn get_original_url(url: &str) -> Option<String> {
+        if !url.contains("mmbiz.qpic.cn") {
+            return None;
+        }
+        let url = url.split('#').next()?;
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        // The last path segment is the requested width, 0 means the original
+        let path = match path.rsplit_once('/') {
+            Some((dir, size)) if size.chars().all(|c| c.is_ascii_digit()) => format!("{dir}/0"),
+            _ => path.to_string(),
+        };
+        // Drop the parameters that ask for recompression
+        let query = query
+            .split('&')
+            .filter(|param| {
+                let key = param.split('=').next().unwrap_or_default();
+                !param.is_empty()
+                    && !matches!(key, "tp" | "wxfrom" | "wx_lazy" | "wx_co" | "watermark")
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        let original = if query.is_empty() {
+            path
+        } else {
+            format!("{path}?{query}")
+        };
+        Some(original).filter(|original| original != url)
+    }
+
+    fn get_img_index(url: &str) -> Option<u32> {
+        url.split_once("#imgIndex=")
+            .and_then(|(_, idx)| idx.split(['&', '#']).next())
+            .and_then(|idx| idx.parse().ok())
+    }
+
+    fn is_sheet_image(url: &str, rules: &config::ImageRules) -> bool {
+        let format = Self::get_wx_fmt(url).and_then(image_format::ImageFormat::from_wx_fmt);
+        rules.matches_url(url, format)
+    }
+
+    /// The sheet candidates of the article, and the `imgIndex` of its other images.
+    fn get_sheets(
+        document: &scraper::Html,
+        rules: &config::ImageRules,
+    ) -> anyhow::Result<(Vec<SheetImage>, Vec<u32>)> {
+        // Weixin article images do not always use the same class/attribute combination.
+        // By default prefer images inside the article body, and fall back to any image-like
+        // nodes that expose a network URL.
+        let selector =
+            scraper::Selector::parse(&rules.region).map_err(|_| errors::SheetError::ParseFailed)?;
+        let mut seen = HashSet::new();
+        let (sheets, others): (Vec<_>, Vec<_>) = document
+            .select(&selector)
+            .filter_map(Self::get_image_url)
+            .filter(|src| seen.insert(src.clone()))
+            .partition(|src| Self::is_sheet_image(src, rules));
+        let mut rejected = others
+            .iter()
+            .filter_map(|url| Self::get_img_index(url))
+            .collect::<Vec<_>>();
+        let selected = rules.select_position(sheets.clone());
+        rejected.extend(
+            sheets
+                .iter()
+                .filter(|url| !selected.contains(url))
+                .filter_map(|url| Self::get_img_index(url)),
+        );
+        let mut sheets = selected
+            .into_iter()
+            .map(|url| SheetImage {
+                img_index: Self::get_img_index(&url),
+                url,
+            })
+            .collect::<Vec<_>>();
+        // Keep the article order, but trust imgIndex over the DOM order when it exists
+        sheets.sort_by_key(|sheet| sheet.img_index.unwrap_or(u32::MAX));
+        for sheet in &sheets {
+            log::info!("Parsed sheet URL: {} ({:?})", sheet.url, sheet.img_index);
+        }
+        Ok((sheets, rejected))
+    }
+
+    /// The indexes between the first and the last page that are neither a
+    /// page nor a rejected image, where pages may be missing.
+    fn get_missing_img_indexes(pages: &[u32], rejected: &[u32]) -> Vec<u32> {
+        let (Some(first), Some(last)) = (pages.iter().min(), pages.iter().max()) else {
+            return Vec::new();
+        };
+        (*first..*last)
+            .filter(|idx| !pages.contains(idx) && !rejected.contains(idx))
+            .collect()
+    }
+
+    /// Read the publish time from the `ct` timestamp, or else from the date shown in the article.
+    fn get_publish_time(html: &str, document: &scraper::Html) -> Option<i64> {
+        if let Some(ct) = Self::get_js_var(html, "ct").and_then(|ct| ct.parse().ok()) {
+            return Some(ct);
+        }
+        let date = Self::get_js_var(html, "publish_time")
+            .or_else(|| Self::get_selector_text(document, "#publish_time"))?;
+        // Weixin shows the time in China
+        let offset = chrono::FixedOffset::east_opt(8 * 3600)?;
+        let naive = ["%Y-%m-%d %H:%M", "%Y年%m月%d日 %H:%M"]
+            .iter()
+            .find_map(|format| chrono::NaiveDateTime::parse_from_str(&date, format).ok())
+            .or_else(|| {
+                ["%Y-%m-%d", "%Y年%m月%d日"].iter().find_map(|format| {
+                    chrono::NaiveDate::parse_from_str(&date, format)
+                        .ok()
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                })
+            })?;
+        Some(naive.and_local_timezone(offset).single()?.timestamp())
+    }
+
+    /// Collect the text of an element, starting a new line at every block
+    /// element. An empty paragraph, which separates stanzas, is a `None`.
+    fn collect_lines(element: scraper::ElementRef<'_>, lines: &mut Vec<Option<String>>) {
+        let name = element.value().name();
+        if matches!(name, "script" | "style") {
+            return;
+        }
+        let is_block = matches!(
+            name,
+            "p" | "div" | "section" | "br" | "li" | "blockquote" | "h1" | "h2" | "h3" | "h4"
+        );
+        if name == "p" && element.text().all(|text| text.trim().is_empty()) {
+            lines.push(None);
+            return;
+        }
+        if is_block {
+            lines.push(Some(String::new()));
+        }
+        for child in element.children() {
+            if let Some(child) = scraper::ElementRef::wrap(child) {
+                Self::collect_lines(child, lines);
+            } else if let Some(text) = child.value().as_text() {
+                match lines.last_mut() {
+                    Some(Some(line)) => line.push_str(text),
+                    _ => lines.push(Some(text.to_string())),
+                }
+            }
+        }
+        if is_block {
+            lines.push(Some(String::new()));
+        }
+    }
+
+    /// Text of the article body, one paragraph per line and an empty line
+    /// between groups of paragraphs.
+    fn get_body_text(document: &scraper::Html) -> Option<String> {
+        let selector = scraper::Selector::parse("#js_content").ok()?;
+        let mut lines = Vec::new();
+        Self::collect_lines(document.select(&selector).next()?, &mut lines);
+        let mut body = Vec::<String>::new();
+        for line in lines {
+            match line {
+                Some(line) => {
+                    let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if !line.is_empty() {
+                        body.push(line);
+                    }
+                }
+                None => {
+                    if body.last().is_some_and(|last| !last.is_empty()) {
+                        body.push(String::new());
+                    }
+                }
+            }
+        }
+        let body = body.join("\n").trim().to_string();
+        (!body.is_empty()).then_some(body)
+    }
+
+    pub async fn try_new(
+        url: String,
+        video: Option<String>,
+        config: &config::Config,
+    ) -> anyhow::Result<Sheet> {
         log::info!("The URL: {url}");
 
         // Use firefox to load the URL
@@ -67,80 +479,531 @@ impl Sheet {
         let html = driver.source().await?;
         let document = scraper::Html::parse_document(&html);
 
-        // Get the title
-        // Get the inner_html under h1
-        let selector =
-            scraper::Selector::parse("h1").map_err(|_| errors::SheetError::ParseFailed)?;
-        let title = document
-            .select(&selector)
-            .nth(0) // Get first element
-            .ok_or(errors::SheetError::GetFailed("sheet title".to_string()))?
-            .text()
-            .collect::<Vec<_>>()
-            .join(" ");
-        let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
-        let splits = title
-            .split('|')
-            .map(str::trim)
-            .filter(|part| !part.is_empty())
-            .collect::<Vec<_>>();
-        let title = if splits.len() >= 2 {
-            format!("{} - {}", splits[1], splits[0])
-        } else {
-            title
-        };
+        // Get the account name and the article cover
+        let account = Self::get_selector_text(&document, "#js_name")
+            .or_else(|| Self::get_js_var(&html, "nickname"));
+        log::info!("Parsed account: {account:?}");
+        let cover = Self::get_js_var(&html, "msg_cdn_url")
+            .or_else(|| Self::get_meta_content(&document, "og:image"));
+        log::info!("Parsed cover URL: {cover:?}");
+        let author = Self::get_selector_text(&document, "#js_author_name")
+            .or_else(|| Self::get_meta_content(&document, "author"))
+            .or_else(|| Self::get_js_var(&html, "author"));
+        log::info!("Parsed author: {author:?}");
+        let publish_time = Self::get_publish_time(&html, &document);
+        log::info!("Parsed publish time: {publish_time:?}");
+        let description = Self::get_meta_content(&document, "og:description")
+            .or_else(|| Self::get_meta_content(&document, "description"))
+            .or_else(|| Self::get_js_var(&html, "msg_desc"));
+        let body = Self::get_body_text(&document);
+        let profile = config.get_profile(account.as_deref()).clone();
+        log::info!("Using profile: {}", profile.name);
+
+        let raw_title = Self::get_raw_title(&html, &document)
+            .ok_or(errors::SheetError::GetFailed("sheet title".to_string()))?;
+        let title::Title {
+            name: title,
+            parts: title_parts,
+        } = title::parse(&raw_title, &profile.title);
+        if title.is_empty() {
+            return Err(errors::SheetError::GetFailed("sheet title".to_string()).into());
+        }
         log::info!("Parsed title: {title}");
 
-        // Get the accompaniment
-        // Get the attr voice_encode_fileid of mpvoice
-        let selector = scraper::Selector::parse("mp-common-mpaudio")
-            .map_err(|_| errors::SheetError::ParseFailed)?;
-        let voice_id = document
-            .select(&selector)
-            .nth(0)
-            .ok_or(errors::SheetError::GetFailed(
-                "accompaniment url".to_string(),
-            ))?
-            .value()
-            .attr("voice_encode_fileid")
-            .ok_or(errors::SheetError::GetFailed(
-                "accompaniment url".to_string(),
-            ))?;
-        let accompaniment = format!("https://res.wx.qq.com/voice/getvoice?mediaid={voice_id}");
-        log::info!("Parsed voice URL: {accompaniment}");
+        // Get the audio tracks
+        let audios = Self::get_audios(&document)?;
 
-        // Get the url of video
-        let video = Downloader20251220::get_url_by_index(index).ok();
-        log::info!("Parsed video URL: {video:?}");
+        log::info!("Video URL: {video:?}");
 
         // Get the music sheet
-        // Weixin article images do not always use the same class/attribute combination.
-        // Prefer images inside the article body, and fall back to any image-like nodes
-        // that expose a network URL.
-        let selector = scraper::Selector::parse("#js_content img, .rich_media_content img, img")
-            .map_err(|_| errors::SheetError::ParseFailed)?;
-        let mut seen = std::collections::HashSet::new();
-        let sheets = document
-            .select(&selector)
-            .filter_map(Self::get_image_url)
-            .filter(|src| Self::is_sheet_image(src))
-            .filter(|src| seen.insert(src.clone()))
-            .collect::<Vec<String>>();
-        log::info!("Parsed sheet URL: {sheets:?}");
+        let (sheets, rejected_img_indexes) = Self::get_sheets(&document, &profile.image)?;
         Ok(Sheet {
             url,
             title,
-            accompaniment,
+            title_parts,
+            account,
+            cover,
+            audios,
             video,
             sheets,
+            rejected_img_indexes,
+            profile,
+            author,
+            publish_time,
+            description,
+            body,
+            output: config.output.clone(),
+            pdf: config.pdf.clone(),
+            omr: config.omr.clone(),
+            progress: jobs::Progress::default(),
+            video_optional: false,
         })
     }
 
-    pub async fn download(&self, path: &str) -> anyhow::Result<()> {
+    /// Report the progress of the download to a job of the API.
+    pub fn set_progress(&mut self, progress: jobs::Progress) {
+        self.progress = progress;
+    }
+
+    /// Skip the video instead of failing when there is no video URL, as for
+    /// jobs submitted without one.
+    pub fn set_video_optional(&mut self) {
+        self.video_optional = true;
+    }
+
+    async fn download_cover(&self) -> Option<Vec<u8>> {
+        let cover = self.cover.clone()?;
+        let resp = reqwest::get(cover).await.ok()?.error_for_status().ok()?;
+        resp.bytes().await.ok().map(|binary| binary.to_vec())
+    }
+
+    async fn download_audios(
+        &self,
+        path: &str,
+        manifest: &mut manifest::Manifest,
+    ) -> anyhow::Result<()> {
+        log::info!("Dowloading audio tracks...");
+        for audio in &self.audios {
+            self.progress
+                .asset("audio", &audio.url, jobs::State::Queued);
+        }
+        for (audio, file_name) in self.audios.iter().zip(self.get_audio_file_names()) {
+            log::info!("Dowloading {file_name}...");
+            self.progress
+                .asset("audio", &audio.url, jobs::State::Running);
+            let duration =
+                match Self::download_audio(&audio.url, &format!("{path}/{file_name}")).await {
+                    Ok(duration) => duration,
+                    Err(e) => {
+                        self.progress.asset_failed("audio", &audio.url, &e);
+                        return Err(e);
+                    }
+                };
+            self.progress.asset_done("audio", &audio.url, &file_name);
+            manifest.audios.push(manifest::AudioEntry {
+                name: audio.name.clone(),
+                file: file_name,
+                url: audio.url.clone(),
+                accompaniment: audio.accompaniment,
+                duration,
+            });
+        }
+        Ok(())
+    }
+
+    /// Download an audio track into the file and return its duration.
+    async fn download_audio(url: &str, file_path: &str) -> anyhow::Result<f64> {
+        let resp = reqwest::get(url).await?.error_for_status()?;
+        let binary = resp.bytes().await?;
+        // The server may answer an error page with status 200
+        let duration = audio::probe_mp3(&binary)?;
+        let mut file = std::fs::File::create(file_path)?;
+        file.write_all(&binary)?;
+        Ok(duration)
+    }
+
+    /// Download an image and check it against the image rules of the profile.
+    async fn fetch_sheet_image(
+        &self,
+        url: &str,
+    ) -> anyhow::Result<Option<(Vec<u8>, image_format::ImageFormat)>> {
+        let resp = reqwest::get(url).await?.error_for_status()?;
+        let binary = resp.bytes().await?;
+        Ok(self
+            .get_sheet_image_format(&binary)
+            .map(|format| (binary.to_vec(), format)))
+    }
+
+    /// Decide whether a downloaded candidate is a new page of the score and
+    /// return its classifier score. The candidates that are not pages are
+    /// recorded in the blocklist.
+    fn check_candidate(
+        &self,
+        url: &str,
+        binary: &[u8],
+        hash: Option<phash::ImageHash>,
+        accepted: &[phash::ImageHash],
+        blocklist: &mut phash::Blocklist,
+    ) -> Option<f64> {
+        let rules = &self.profile.image;
+        if let Some(hash) = hash {
+            if accepted
+                .iter()
+                .any(|other| other.distance(&hash) <= rules.duplicate_distance)
+            {
+                log::info!("Skipping duplicate image candidate {url}");
+                return None;
+            }
+            if blocklist.is_blocked(
+                &hash,
+                &self.url,
+                rules.blocklist_min_articles,
+                rules.duplicate_distance,
+            ) {
+                log::info!("Skipping image candidate {url} found in other articles");
+                blocklist.record(hash, &self.url, rules.duplicate_distance);
+                return None;
+            }
+        }
+        let classification = classifier::classify(binary);
+        if classification.score < rules.min_sheet_score {
+            log::info!(
+                "Rejected image candidate {url}: score {:.2}, {}",
+                classification.score,
+                classification.reason
+            );
+            if let Some(hash) = hash {
+                blocklist.record(hash, &self.url, rules.duplicate_distance);
+            }
+            return None;
+        }
+        log::info!(
+            "Accepted image candidate {url}: score {:.2}, {}",
+            classification.score,
+            classification.reason
+        );
+        Some(classification.score)
+    }
+
+    /// Download the sheet pages and return the content of the first one.
+    async fn download_sheets(
+        &self,
+        path: &str,
+        manifest: &mut manifest::Manifest,
+        blocklist: &mut phash::Blocklist,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        log::info!("Dowloading sheets...");
+        let naming = self.naming();
+        let mut saved_idx = 1;
+        let mut hashes = Vec::new();
+        let mut first_page = None;
+        let mut rejected = self.rejected_img_indexes.clone();
+        for sheet in &self.sheets {
+            self.progress
+                .asset("image", &sheet.url, jobs::State::Queued);
+        }
+        for sheet in &self.sheets {
+            self.progress
+                .asset("image", &sheet.url, jobs::State::Running);
+            // Prefer the original resolution, but fall back to the URL of the page
+            let mut fetched = None;
+            if let Some(original) = Self::get_original_url(&sheet.url) {
+                match self.fetch_sheet_image(&original).await {
+                    Ok(Some(image)) => fetched = Some((original, image)),
+                    Ok(None) => log::info!("Original image {original} failed validation"),
+                    Err(e) => log::info!("Failed to get original image {original}: {e}"),
+                }
+            }
+            let fetched = match fetched {
+                Some(fetched) => Some(fetched),
+                None => match self.fetch_sheet_image(&sheet.url).await {
+                    Ok(image) => image.map(|image| (sheet.url.clone(), image)),
+                    Err(e) => {
+                        self.progress.asset_failed("image", &sheet.url, &e);
+                        return Err(e);
+                    }
+                },
+            };
+            let Some((download_url, (binary, format))) = fetched else {
+                log::info!("Skipping non-sheet image candidate {}", sheet.url);
+                self.progress
+                    .asset("image", &sheet.url, jobs::State::Skipped);
+                rejected.extend(sheet.img_index);
+                continue;
+            };
+            let hash = phash::ImageHash::compute(&binary);
+            let Some(score) = self.check_candidate(&sheet.url, &binary, hash, &hashes, blocklist)
+            else {
+                self.progress
+                    .asset("image", &sheet.url, jobs::State::Skipped);
+                rejected.extend(sheet.img_index);
+                continue;
+            };
+            if let Some(hash) = hash {
+                hashes.push(hash);
+            }
+            let file_name = naming.page(saved_idx, format.extension());
+            let mut file = std::fs::File::create(format!("{path}/{file_name}"))?;
+            file.write_all(&binary)?;
+            self.progress.asset_done("image", &sheet.url, &file_name);
+            manifest.pages.push(manifest::PageEntry {
+                file: file_name,
+                url: sheet.url.clone(),
+                download_url,
+                img_index: sheet.img_index,
+                score,
+                hash: hash.map(phash::ImageHash::to_hex).unwrap_or_default(),
+                originals: Vec::new(),
+                musicxml: Vec::new(),
+                omr_error: None,
+            });
+            if first_page.is_none() {
+                first_page = Some(binary);
+            }
+            saved_idx += 1;
+        }
+
+        let indexes = manifest
+            .pages
+            .iter()
+            .filter_map(|page| page.img_index)
+            .collect::<Vec<_>>();
+        manifest.missing_img_indexes = Self::get_missing_img_indexes(&indexes, &rejected);
+        if !manifest.missing_img_indexes.is_empty() {
+            log::warn!(
+                "Sheet pages may be missing, no page for imgIndex {:?}",
+                manifest.missing_img_indexes
+            );
+        }
+        Ok(first_page)
+    }
+
+    /// Split tall images and join narrow strips into pages numbered in order,
+    /// keeping the downloaded images they are made from under `original/`.
+    /// Images that are already pages keep their downloaded content.
+    fn normalize_layout(
+        &self,
+        path: &str,
+        manifest: &mut manifest::Manifest,
+    ) -> anyhow::Result<()> {
+        let images = manifest
+            .pages
+            .iter()
+            .map(|page| Ok(image::open(format!("{path}/{}", page.file))?.to_rgb8()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let Some(pages) = layout::normalize(&images, &self.profile.layout)? else {
+            return Ok(());
+        };
+        log::info!(
+            "Laid out {} images as {} pages",
+            manifest.pages.len(),
+            pages.len()
+        );
+
+        let naming = self.naming();
+        let files = manifest
+            .pages
+            .iter()
+            .map(|page| page.file.clone())
+            .collect::<Vec<_>>();
+        let names = layout::file_names(&pages, &files, |number, extension| {
+            naming.page(number, extension)
+        })?;
+
+        let kept = pages
+            .iter()
+            .filter(|page| page.image.is_none())
+            .map(|page| page.sources[0])
+            .collect::<HashSet<_>>();
+        std::fs::create_dir_all(format!("{path}/{ORIGINAL_FOLDER}"))?;
+        for (idx, page) in manifest.pages.iter_mut().enumerate() {
+            if kept.contains(&idx) {
+                continue;
+            }
+            let original = format!("{ORIGINAL_FOLDER}/{}", page.file);
+            std::fs::rename(
+                format!("{path}/{}", page.file),
+                format!("{path}/{original}"),
+            )?;
+            page.originals = vec![original];
+        }
+        // Kept pages are renumbered in two steps, as one may take the name
+        // another still has
+        for (page, name) in pages.iter().zip(&names) {
+            if page.image.is_none() && files[page.sources[0]] != *name {
+                std::fs::rename(
+                    format!("{path}/{}", files[page.sources[0]]),
+                    format!("{path}/{name}.layout"),
+                )?;
+            }
+        }
+        for (page, name) in pages.iter().zip(&names) {
+            if page.image.is_none() && files[page.sources[0]] != *name {
+                std::fs::rename(format!("{path}/{name}.layout"), format!("{path}/{name}"))?;
+            }
+        }
+
+        let mut downloaded = std::mem::take(&mut manifest.pages)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        for (page, file_name) in pages.into_iter().zip(names) {
+            let Some(image) = page.image else {
+                if let Some(mut entry) = downloaded[page.sources[0]].take() {
+                    entry.file = file_name;
+                    manifest.pages.push(entry);
+                }
+                continue;
+            };
+            image.save(format!("{path}/{file_name}"))?;
+            let sources = page
+                .sources
+                .iter()
+                .filter_map(|source| downloaded[*source].as_ref())
+                .collect::<Vec<_>>();
+            manifest.pages.push(manifest::PageEntry {
+                file: file_name,
+                url: sources[0].url.clone(),
+                download_url: sources[0].download_url.clone(),
+                img_index: sources[0].img_index,
+                score: sources
+                    .iter()
+                    .map(|source| source.score)
+                    .fold(f64::MAX, f64::min),
+                hash: sources[0].hash.clone(),
+                originals: sources
+                    .iter()
+                    .flat_map(|source| source.originals.clone())
+                    .collect(),
+                musicxml: Vec::new(),
+                omr_error: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Clean up the pages, keeping the downloaded ones under `original/`.
+    fn postprocess_pages(
+        &self,
+        path: &str,
+        manifest: &mut manifest::Manifest,
+    ) -> anyhow::Result<()> {
+        log::info!("Post-processing sheets...");
+        std::fs::create_dir_all(format!("{path}/{ORIGINAL_FOLDER}"))?;
+        for page in &mut manifest.pages {
+            // Pages made by the layout step already have their originals kept
+            let source = if page.originals.is_empty() {
+                let original = format!("{ORIGINAL_FOLDER}/{}", page.file);
+                std::fs::rename(
+                    format!("{path}/{}", page.file),
+                    format!("{path}/{original}"),
+                )?;
+                page.originals = vec![original.clone()];
+                original
+            } else {
+                page.file.clone()
+            };
+            let binary = std::fs::read(format!("{path}/{source}"))?;
+            let processed = postprocess::process(&binary, &self.profile.postprocess)?;
+            let stem = page
+                .file
+                .rsplit_once('.')
+                .map_or(page.file.as_str(), |(stem, _)| stem);
+            page.file = format!("{stem}.png");
+            let mut file = std::fs::File::create(format!("{path}/{}", page.file))?;
+            file.write_all(&processed)?;
+        }
+        Ok(())
+    }
+
+    /// Recognize the music of every page, recording the failures in the manifest.
+    fn recognize_pages(&self, path: &str, manifest: &mut manifest::Manifest) {
+        log::info!("Recognizing music with {}...", self.omr.command);
+        for page in &mut manifest.pages {
+            match omr::recognize(path, &page.file, &self.omr) {
+                Ok(files) => page.musicxml = files,
+                Err(e) => {
+                    log::warn!("Failed to recognize {}: {e}", page.file);
+                    page.omr_error = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    fn write_pdf(&self, path: &str, manifest: &mut manifest::Manifest) -> anyhow::Result<()> {
+        let file_name = self
+            .naming()
+            .file(&self.pdf.file_name, &[], "sheet.pdf", "");
+        log::info!("Creating {file_name}...");
+        let mut builder = pdf::PdfBuilder::new(&self.pdf);
+        for page in &manifest.pages {
+            let binary = std::fs::read(format!("{path}/{}", page.file))?;
+            builder.add_image_page(&binary, None)?;
+        }
+        let info = pdf::DocumentInfo {
+            title: self.title.clone(),
+            author: self.title_parts.get("artist").cloned(),
+            source: self.url.clone(),
+        };
+        let mut file = std::fs::File::create(format!("{path}/{file_name}"))?;
+        file.write_all(&builder.finish(&info))?;
+        self.progress.asset_done("pdf", &file_name, &file_name);
+        manifest.pdf = Some(file_name);
+        Ok(())
+    }
+
+    async fn write_tags(&self, path: &str, first_page: Option<Vec<u8>>) -> anyhow::Result<()> {
+        log::info!("Writing ID3 tags...");
+        let artwork = match first_page {
+            Some(page) => Some(page),
+            None => self.download_cover().await,
+        };
+        let info = audio::TagInfo {
+            title: self
+                .title_parts
+                .get("song")
+                .cloned()
+                .unwrap_or_else(|| self.title.clone()),
+            artist: self.title_parts.get("artist").cloned(),
+            album: self.account.clone(),
+            comment: self.url.clone(),
+            artwork: artwork.as_deref(),
+        };
+        audio::write_tags(&format!("{path}/{}", self.naming().accompaniment()), &info)
+    }
+
+    /// Download the video with yt-dlp and record the file it wrote. A missing
+    /// video URL is an error unless the video is optional.
+    async fn download_video(
+        &self,
+        path: &str,
+        manifest: &mut manifest::Manifest,
+    ) -> anyhow::Result<()> {
+        let Some(video) = self.video.clone() else {
+            if !self.video_optional {
+                return Err(errors::SheetError::GetFailed("video url".to_string()).into());
+            }
+            log::warn!("No video URL, skipping the video");
+            self.progress.asset("video", "", jobs::State::Skipped);
+            return Ok(());
+        };
+        log::info!("Dowloading video...");
+        let stem = self.naming().video();
+        self.progress.asset("video", &video, jobs::State::Running);
+        if let Err(e) =
+            Downloader20251220::download_video(stem.clone(), video.clone(), path.to_string(), 0)
+                .await
+        {
+            self.progress.asset_failed("video", &video, &e);
+            return Err(e);
+        }
+        manifest.video = std::fs::read_dir(path)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .find(|file| {
+                file.file_stem()
+                    .is_some_and(|name| name.to_string_lossy() == stem)
+                    && file.extension().is_some_and(|extension| {
+                        !matches!(extension.to_str(), Some("part" | "ytdl"))
+                    })
+            })
+            .and_then(|file| Some(file.file_name()?.to_string_lossy().to_string()));
+        self.progress.asset_done(
+            "video",
+            &video,
+            manifest.video.as_deref().unwrap_or_default(),
+        );
+        Ok(())
+    }
+
+    pub async fn download(&self, output: &str) -> anyhow::Result<()> {
+        let mut blocklist = phash::Blocklist::load(output)?;
+
         // Create folder
         log::info!("Creating folder...");
-        let path = format!("{}/{}", path, self.title.as_str());
+        let path = paths::unique_folder(output, &self.naming().folder(), &self.url);
         std::fs::create_dir_all(&path)?;
+        self.progress.folder(&path);
 
         // Create README
         {
@@ -148,44 +1011,147 @@ impl Sheet {
             let mut file = std::fs::File::create(format!("{path}/README"))?;
             file.write_all(self.url.as_bytes())?;
         }
+        self.write_metadata(&path)?;
 
-        // Download accompaniment
-        {
-            log::info!("Dowloading accompaniment...");
-            let resp = reqwest::get(self.accompaniment.clone()).await?;
-            let binary = resp.bytes().await?;
-            let mut file = std::fs::File::create(format!("{path}/伴奏.mp3"))?;
-            file.write_all(&binary)?;
-        }
+        let mut manifest = manifest::Manifest {
+            url: self.url.clone(),
+            title: self.title.clone(),
+            audios: Vec::new(),
+            pages: Vec::new(),
+            missing_img_indexes: Vec::new(),
+            pdf: None,
+            video_url: self.video.clone(),
+            video: None,
+        };
+
+        // Download audio tracks
+        self.progress.stage("audio");
+        self.download_audios(&path, &mut manifest).await?;
 
         // Download sheet
-        {
-            log::info!("Dowloading sheets...");
-            let mut saved_idx = 1;
-            for sheet in self.sheets.clone() {
-                let resp = reqwest::get(sheet).await?;
-                let binary = resp.bytes().await?;
-                if !Self::is_likely_sheet_png(&binary) {
-                    log::info!("Skipping non-sheet image candidate");
-                    continue;
-                }
-                let mut file = std::fs::File::create(format!("{path}/{saved_idx}.png"))?;
-                file.write_all(&binary)?;
-                saved_idx += 1;
-            }
+        self.progress.stage("sheets");
+        let first_page = self
+            .download_sheets(&path, &mut manifest, &mut blocklist)
+            .await?;
+        blocklist.save()?;
+        if self.profile.layout.enabled && !manifest.pages.is_empty() {
+            self.progress.stage("layout");
+            self.normalize_layout(&path, &mut manifest)?;
+        }
+        if self.profile.postprocess.enabled {
+            self.progress.stage("postprocess");
+            self.postprocess_pages(&path, &mut manifest)?;
+        }
+
+        if self.omr.enabled {
+            self.progress.stage("omr");
+            self.recognize_pages(&path, &mut manifest);
+        }
+
+        // Assemble the pages into a printable PDF
+        if self.pdf.enabled && !manifest.pages.is_empty() {
+            self.progress.stage("pdf");
+            self.write_pdf(&path, &mut manifest)?;
+        }
+        manifest.save(&path)?;
+
+        // Tag the accompaniment
+        self.progress.stage("tags");
+        if let Err(e) = self.write_tags(&path, first_page).await {
+            log::warn!("Failed to write the ID3 tags: {e}");
         }
 
         // Download video
+        self.progress.stage("video");
+        let video = self.download_video(&path, &mut manifest).await;
+        manifest.save(&path)?;
+
+        // Record the song in the catalog, even if the video is missing. The
+        // song is downloaded anyway and `reindex` repairs the catalog.
+        self.progress.stage("catalog");
+        if let Err(e) = catalog::Catalog::open(output).and_then(|mut catalog| catalog.update(&path))
         {
-            log::info!("Dowloading video...");
-            if let Some(video) = self.video.clone() {
-                // Download video as a file
-                Downloader20251220::download_video(self.title.clone(), video, path, 0).await?;
-            } else {
-                return Err(errors::SheetError::GetFailed("video url".to_string()).into());
-            }
+            log::warn!("Failed to record the song in the catalog: {e}");
         }
 
-        Ok(())
+        video
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn original_url() {
+        let cases = [
+            (
+                "https://mmbiz.qpic.cn/mmbiz_png/abc/640?wx_fmt=png&tp=webp&wxfrom=5&wx_lazy=1&wx_co=1#imgIndex=2",
+                Some("https://mmbiz.qpic.cn/mmbiz_png/abc/0?wx_fmt=png"),
+            ),
+            (
+                "https://mmbiz.qpic.cn/mmbiz_jpg/abc/0?wx_fmt=jpeg&watermark=1&from=appmsg",
+                Some("https://mmbiz.qpic.cn/mmbiz_jpg/abc/0?wx_fmt=jpeg&from=appmsg"),
+            ),
+            (
+                "https://mmbiz.qpic.cn/mmbiz_gif/abc/300?tp=webp",
+                Some("https://mmbiz.qpic.cn/mmbiz_gif/abc/0"),
+            ),
+            // A last segment that is not a size is kept
+            (
+                "https://mmbiz.qpic.cn/mmbiz_png/abc/image?wx_fmt=png&tp=webp",
+                Some("https://mmbiz.qpic.cn/mmbiz_png/abc/image?wx_fmt=png"),
+            ),
+            // Already the original, or not an mmbiz image: fall back to the URL
+            ("https://mmbiz.qpic.cn/mmbiz_png/abc/0?wx_fmt=png", None),
+            ("https://example.com/sheet/640?tp=webp", None),
+        ];
+        for (url, original) in cases {
+            assert_eq!(Sheet::get_original_url(url).as_deref(), original, "{url}");
+        }
+    }
+
+    #[test]
+    fn img_index_of_url() {
+        let url = "https://mmbiz.qpic.cn/mmbiz_png/abc/640?wx_fmt=png&from=appmsg#imgIndex=12";
+        assert_eq!(Sheet::get_img_index(url), Some(12));
+        assert_eq!(
+            Sheet::get_img_index("https://a/b.png#imgIndex=3&x=1"),
+            Some(3)
+        );
+        assert_eq!(Sheet::get_img_index("https://a/b.png#imgIndex=x"), None);
+        assert_eq!(Sheet::get_img_index("https://a/b.png?wx_fmt=png"), None);
+    }
+
+    #[test]
+    fn missing_img_indexes_leave_out_rejected_images() {
+        // A QR code at 3 and a banner at 5 between the pages
+        assert_eq!(
+            Sheet::get_missing_img_indexes(&[2, 4, 6, 8], &[0, 3, 5, 9]),
+            vec![7]
+        );
+        assert_eq!(Sheet::get_missing_img_indexes(&[4, 1, 2], &[]), vec![3]);
+        assert!(Sheet::get_missing_img_indexes(&[], &[1]).is_empty());
+    }
+
+    fn raw_title(html: &str) -> Option<String> {
+        Sheet::get_raw_title(html, &scraper::Html::parse_document(html))
+    }
+
+    #[test]
+    fn title_fallbacks() {
+        let heading = r#"<html><head><title>Page</title>
+            <meta property="og:title" content="Meta"></head>
+            <body><h1> 主恩浩大 </h1></body></html>"#;
+        assert_eq!(raw_title(heading).as_deref(), Some("主恩浩大"));
+        let og_title = r#"<html><head><title>Page</title>
+            <meta property="og:title" content="奇异恩典"></head><body><h1> </h1></body></html>"#;
+        assert_eq!(raw_title(og_title).as_deref(), Some("奇异恩典"));
+        let msg_title = r#"<html><head><title>Page</title></head><body>
+            <script>var msg_title = htmlDecode("Amazing Grace");</script></body></html>"#;
+        assert_eq!(raw_title(msg_title).as_deref(), Some("Amazing Grace"));
+        let title = "<html><head><title>奇异恩典 | 赞美之泉</title></head><body></body></html>";
+        assert_eq!(raw_title(title).as_deref(), Some("奇异恩典 | 赞美之泉"));
+        assert_eq!(raw_title("<html><body><p>text</p></body></html>"), None);
     }
 }
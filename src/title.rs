@@ -0,0 +1,190 @@
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use crate::config::TitleOptions;
+
+const PART_SEPARATORS: [char; 2] = ['|', '｜'];
+// Brackets around a decoration such as 【原创】 or around the song name
+const DECORATION_BRACKETS: [(char, char); 2] = [('【', '】'), ('〖', '〗')];
+// Bracketed words that are decorations rather than a part of the title
+const DECORATION_TAGS: [&str; 20] = [
+    "原创",
+    "首发",
+    "独家",
+    "新歌",
+    "推荐",
+    "分享",
+    "转载",
+    "每日一歌",
+    "诗歌",
+    "赞美诗",
+    "敬拜",
+    "歌谱",
+    "曲谱",
+    "乐谱",
+    "简谱",
+    "五线谱",
+    "伴奏",
+    "视频",
+    "音频",
+    "MV",
+];
+// Quotes around the song name that are dropped
+const QUOTES: [char; 6] = ['「', '」', '『', '』', '《', '》'];
+
+static PLACEHOLDER: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"\{(\w+)\}").expect("valid placeholder pattern"));
+
+/// The title of an article with its named parts.
+pub struct Title {
+    /// Name made from the parts with the template
+    pub name: String,
+    pub parts: BTreeMap<String, String>,
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(
+        u32::from(c),
+        0x1F000..=0x1FAFF   // Emoticons, pictographs, flags
+        | 0x2600..=0x27BF   // Symbols and dingbats
+        | 0x2B00..=0x2BFF   // Stars and arrows
+        | 0xFE00..=0xFE0F   // Variation selectors
+        | 0x200D            // Zero width joiner
+        | 0xE0020..=0xE007F // Tags
+    )
+}
+
+/// Remove decorations and emoji from a part of the title.
+fn clean(part: &str) -> String {
+    // Drop the bracketed tags and keep the content of the other brackets
+    let mut stripped = String::new();
+    let mut rest = part;
+    while let Some((start, open, close)) = DECORATION_BRACKETS
+        .iter()
+        .filter_map(|(open, close)| Some((rest.find(*open)?, *open, *close)))
+        .min_by_key(|(start, _, _)| *start)
+    {
+        let inner = &rest[start + open.len_utf8()..];
+        let Some(end) = inner.find(close) else {
+            break;
+        };
+        stripped.push_str(&rest[..start]);
+        stripped.push(' ');
+        if !DECORATION_TAGS.contains(&inner[..end].trim()) {
+            stripped.push_str(&inner[..end]);
+            stripped.push(' ');
+        }
+        rest = &inner[end + close.len_utf8()..];
+    }
+    stripped.push_str(rest);
+    // Keep the content of the brackets if nothing else is left
+    let part = if stripped.trim().is_empty() {
+        part
+    } else {
+        &stripped
+    };
+    part.chars()
+        .filter(|c| {
+            !is_emoji(*c)
+                && !QUOTES.contains(c)
+                && !DECORATION_BRACKETS
+                    .iter()
+                    .any(|(open, close)| c == open || c == close)
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Replace the placeholders of a segment, or return `None` if a part is missing.
+fn render(segment: &str, parts: &BTreeMap<String, String>) -> Option<String> {
+    let mut missing = false;
+    let rendered = PLACEHOLDER.replace_all(segment, |caps: &regex::Captures| {
+        parts.get(&caps[1]).cloned().unwrap_or_else(|| {
+            missing = true;
+            String::new()
+        })
+    });
+    (!missing).then(|| rendered.trim().to_string())
+}
+
+/// Split a title like `主恩浩大 | 赞美之泉` into the parts named by the
+/// options and format it with the template.
+pub fn parse(raw: &str, options: &TitleOptions) -> Title {
+    let splits = raw
+        .split(PART_SEPARATORS)
+        .map(clean)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    let mut parts = options
+        .parts
+        .iter()
+        .zip(&splits)
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect::<BTreeMap<_, _>>();
+    let full = splits.join(" | ");
+    parts.insert("title".to_string(), full.clone());
+
+    let name = options
+        .template
+        .iter()
+        .filter_map(|segment| render(segment, &parts))
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join(&options.separator);
+    Title {
+        name: if name.is_empty() { full } else { name },
+        parts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(parts: &[&str], template: &[&str]) -> TitleOptions {
+        TitleOptions {
+            parts: parts.iter().map(|part| (*part).to_string()).collect(),
+            template: template
+                .iter()
+                .map(|segment| (*segment).to_string())
+                .collect(),
+            separator: " - ".to_string(),
+        }
+    }
+
+    #[test]
+    fn clean_decorations() {
+        assert_eq!(clean("【原创】「主恩浩大」🎵"), "主恩浩大");
+        assert_eq!(clean("【奇异恩典】 赞美诗"), "奇异恩典 赞美诗");
+        assert_eq!(clean("〖新歌〗奇异恩典【伴奏】"), "奇异恩典");
+        assert_eq!(clean("【原创】"), "原创");
+        assert_eq!(clean("【未闭合 标题"), "未闭合 标题");
+    }
+
+    #[test]
+    fn template_maps_parts() {
+        let title = parse(
+            "【原创】主恩浩大 | 小羊诗歌 | 张三",
+            &options(
+                &["song", "artist", "arranger"],
+                &["{song}", "{artist}", "arr. {arranger}"],
+            ),
+        );
+        assert_eq!(title.name, "主恩浩大 - 小羊诗歌 - arr. 张三");
+        assert_eq!(title.parts["song"], "主恩浩大");
+        assert_eq!(title.parts["title"], "主恩浩大 | 小羊诗歌 | 张三");
+    }
+
+    #[test]
+    fn template_leaves_out_missing_parts() {
+        let default = TitleOptions::default();
+        assert_eq!(parse("奇异恩典", &default).name, "奇异恩典");
+        assert_eq!(
+            parse("奇异恩典｜赞美之泉", &default).name,
+            "赞美之泉 - 奇异恩典"
+        );
+        let unknown = options(&["song"], &["{album}"]);
+        assert_eq!(parse("奇异恩典", &unknown).name, "奇异恩典");
+    }
+}
@@ -30,7 +30,7 @@ impl Downloader for Downloader20251220 {
     }
 
     async fn download_video(
-        _title: String,
+        title: String,
         url: String,
         path: String,
         _timeout: u64,
@@ -40,6 +40,8 @@ impl Downloader for Downloader20251220 {
             .arg("bestvideo+bestaudio")
             .arg("-P")
             .arg(path)
+            .arg("-o")
+            .arg(format!("{}.%(ext)s", title.replace('%', "%%")))
             .arg(url)
             .status()?;
         if !status.success() {